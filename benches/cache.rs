@@ -0,0 +1,48 @@
+use criterion::{BatchSize, Criterion};
+use futures::executor::block_on;
+use glimbot::db::cache::{Cache, EvictionStrategy, TimedCache};
+#[cfg(target_env = "gnu")]
+use jemallocator::Jemalloc;
+use rand::distributions::Uniform;
+use rand::{thread_rng, Rng};
+use rayon::prelude::*;
+use std::time::Duration;
+
+#[doc(hidden)]
+#[cfg(target_env = "gnu")]
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+#[macro_use]
+extern crate criterion;
+
+const KEYS: u64 = 64;
+const BATCH_SIZE: usize = 1024;
+
+fn keys() -> Vec<u64> {
+    let dist = Uniform::new(0, KEYS);
+    thread_rng().sample_iter(dist).take(BATCH_SIZE).collect()
+}
+
+fn load<S: EvictionStrategy<u64> + Send + Sync>(cache: &Cache<u64, u64, S>, keys: Vec<u64>) {
+    keys.into_par_iter().for_each(|k| {
+        criterion::black_box(block_on(cache.get_or_insert_with(&k, async move { Ok(k) })).unwrap());
+    });
+}
+
+fn bench_get_or_insert(c: &mut Criterion) {
+    c.bench_function("cache_warm", |b| {
+        let cache = Cache::null();
+        b.iter_batched(keys, |k| load(&cache, k), BatchSize::SmallInput)
+    });
+
+    // Entries expire constantly, so most calls contend for a loader.
+    c.bench_function("cache_expiring", |b| {
+        let cache = TimedCache::new(Duration::from_micros(50));
+        b.iter_batched(keys, |k| load(&cache, k), BatchSize::SmallInput)
+    });
+}
+
+criterion_group!(cache, bench_get_or_insert);
+
+criterion_main!(cache);
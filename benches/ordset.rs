@@ -202,7 +202,7 @@ fn generate_ops() -> impl Iterator<Item = Op<usize>> {
 
     let mut rng = thread_rng();
 
-    let mut i = (0..).map(move |i| i + rng.sample(&jitter));
+    let mut i = (0..).map(move |i| i + rng.sample(jitter));
     std::iter::repeat_with(move || og.select_kind()).filter_map(move |k| k.make_op(&mut i))
 }
 
@@ -0,0 +1,16 @@
+CREATE TABLE sticky_roles
+(
+    guild       BIGINT NOT NULL,
+    target_user BIGINT NOT NULL,
+    role        BIGINT NOT NULL,
+    PRIMARY KEY (guild, target_user, role),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_sticky_guild
+    BEFORE INSERT OR UPDATE
+    ON sticky_roles
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,17 @@
+CREATE TABLE spam_pressure
+(
+    guild       BIGINT           NOT NULL,
+    target_user BIGINT           NOT NULL,
+    pressure    DOUBLE PRECISION NOT NULL,
+    updated_at  TIMESTAMPTZ      NOT NULL,
+    PRIMARY KEY (guild, target_user),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_spam_pressure_guild
+    BEFORE INSERT OR UPDATE
+    ON spam_pressure
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
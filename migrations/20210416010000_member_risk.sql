@@ -0,0 +1,19 @@
+CREATE TABLE member_risk
+(
+    guild         BIGINT           NOT NULL,
+    target_user   BIGINT           NOT NULL,
+    messages      INT              NOT NULL DEFAULT 0,
+    warns         INT              NOT NULL DEFAULT 0,
+    mutes         INT              NOT NULL DEFAULT 0,
+    peak_pressure DOUBLE PRECISION NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild, target_user),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_member_risk_guild
+    BEFORE INSERT OR UPDATE
+    ON member_risk
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
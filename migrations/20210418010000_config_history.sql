@@ -0,0 +1,20 @@
+CREATE TABLE config_history
+(
+    guild      BIGINT      NOT NULL,
+    name       TEXT        NOT NULL,
+    version    INT         NOT NULL,
+    old_value  JSONB,
+    new_value  JSONB,
+    changed_by BIGINT,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild, name, version),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_config_history_guild
+    BEFORE INSERT OR UPDATE
+    ON config_history
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
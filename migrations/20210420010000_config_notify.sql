@@ -0,0 +1,33 @@
+-- Notifies every running Glimbot process when a config value changes, so they can evict it from their caches.
+-- The payload identifies the changed value; an empty payload means every value may have changed.
+CREATE OR REPLACE FUNCTION notify_config_change()
+    RETURNS TRIGGER
+    LANGUAGE plpgsql
+AS
+$$
+BEGIN
+    IF TG_OP = 'TRUNCATE' THEN
+        PERFORM pg_notify('config_changed', '');
+        RETURN NULL;
+    END IF;
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        PERFORM pg_notify('config_changed', json_build_object('guild', OLD.guild, 'name', OLD.name)::TEXT);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        PERFORM pg_notify('config_changed', json_build_object('guild', NEW.guild, 'name', NEW.name)::TEXT);
+    END IF;
+    RETURN NULL;
+END;
+$$;
+
+CREATE TRIGGER notify_config_values
+    AFTER INSERT OR UPDATE OR DELETE
+    ON config_values
+    FOR EACH ROW
+EXECUTE PROCEDURE notify_config_change();
+
+CREATE TRIGGER notify_config_values_truncate
+    AFTER TRUNCATE
+    ON config_values
+    FOR EACH STATEMENT
+EXECUTE PROCEDURE notify_config_change();
@@ -0,0 +1,68 @@
+-- The SQLite schema mirrors the PostgreSQL one. Timestamps are stored as microseconds since the Unix epoch, and
+-- JSON values as text. The joinable role limit is enforced by the storage backend instead of a trigger.
+CREATE TABLE config_values
+(
+    guild INTEGER NOT NULL,
+    name  TEXT    NOT NULL,
+    value TEXT    NOT NULL,
+    PRIMARY KEY (guild, name)
+);
+
+CREATE TABLE config_history
+(
+    guild      INTEGER NOT NULL,
+    name       TEXT    NOT NULL,
+    version    INTEGER NOT NULL,
+    old_value  TEXT,
+    new_value  TEXT,
+    changed_by INTEGER,
+    changed_at INTEGER NOT NULL,
+    PRIMARY KEY (guild, name, version)
+);
+
+CREATE TABLE joinable_roles
+(
+    guild INTEGER NOT NULL,
+    role  INTEGER NOT NULL,
+    PRIMARY KEY (guild, role)
+);
+
+CREATE TABLE timed_events
+(
+    target_user INTEGER NOT NULL,
+    guild       INTEGER NOT NULL,
+    expiry      INTEGER NOT NULL,
+    action      TEXT    NOT NULL,
+    CONSTRAINT unique_timed_event UNIQUE (target_user, guild, expiry, action)
+);
+
+CREATE INDEX timed_events_by_guild ON timed_events (guild);
+CREATE INDEX timed_events_by_time ON timed_events (expiry);
+
+CREATE TABLE sticky_roles
+(
+    guild       INTEGER NOT NULL,
+    target_user INTEGER NOT NULL,
+    role        INTEGER NOT NULL,
+    PRIMARY KEY (guild, target_user, role)
+);
+
+CREATE TABLE spam_pressure
+(
+    guild       INTEGER NOT NULL,
+    target_user INTEGER NOT NULL,
+    pressure    REAL    NOT NULL,
+    updated_at  INTEGER NOT NULL,
+    PRIMARY KEY (guild, target_user)
+);
+
+CREATE TABLE member_risk
+(
+    guild         INTEGER NOT NULL,
+    target_user   INTEGER NOT NULL,
+    messages      INTEGER NOT NULL DEFAULT 0,
+    warns         INTEGER NOT NULL DEFAULT 0,
+    mutes         INTEGER NOT NULL DEFAULT 0,
+    peak_pressure REAL    NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild, target_user)
+);
@@ -1,6 +1,5 @@
 //! Contains implementation of caching for per guild objects.
 
-use arc_swap::access::{Access, Map};
 use arc_swap::ArcSwap;
 
 use std::borrow::Borrow;
@@ -9,10 +8,40 @@ use std::future::Future;
 use std::hash::Hash;
 use std::ops::Deref;
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-pub type CacheValue<V, Tag> = Arc<arc_swap::ArcSwapOption<(Tag, V)>>;
+use tokio::sync::OwnedMutexGuard;
+
+pub type CacheValue<V, Tag> = Arc<CacheEntry<V, Tag>>;
+
+/// A single slot in a cache.
+#[derive(Debug)]
+pub struct CacheEntry<V, Tag> {
+    /// The cached value, along with its eviction tag.
+    value: arc_swap::ArcSwapOption<(Tag, V)>,
+    /// Held while loading the value, so concurrent misses on the same key run a single loader.
+    loading: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<V, Tag> Default for CacheEntry<V, Tag> {
+    fn default() -> Self {
+        CacheEntry {
+            value: Default::default(),
+            loading: Default::default(),
+        }
+    }
+}
+
+/// Checks whether two loads of a cache slot saw the same value.
+fn same_value<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
 
 #[derive(Debug)]
 pub struct Cached<V, Tag>(Arc<(Tag, V)>);
@@ -56,6 +85,19 @@ where
     type Tag: fmt::Debug + Sized + Clone + Send + Sync;
     fn should_evict(&self, t: &Self::Tag) -> bool;
     fn create_tag(&self, k: &K) -> Self::Tag;
+
+    /// Called whenever a value is read from the cache.
+    fn on_access(&self, _t: &Self::Tag) {}
+
+    /// The most entries the cache should hold, if it's bounded.
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    /// How much an entry is worth keeping. When the cache is over capacity, the lowest scoring entries go first.
+    fn score(&self, _t: &Self::Tag) -> u64 {
+        0
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -93,6 +135,144 @@ impl<K: Send + Sync + Hash + Eq + Clone> EvictionStrategy<K> for NullEvictionStr
     fn create_tag(&self, _g: &K) -> Self::Tag {}
 }
 
+/// Tag for the capacity-bounded strategies, recording when an entry was created and how it's been used.
+#[derive(Clone, Debug)]
+pub struct UsageTag {
+    /// When the entry was created.
+    created: Instant,
+    /// The strategy-specific usage, i.e. when it was last used or how often.
+    usage: Arc<AtomicU64>,
+}
+
+/// Evicts the least recently used entries once the cache is over capacity, and optionally entries older than a TTL.
+#[derive(Debug)]
+pub struct LruEvictionStrategy {
+    /// The most entries to keep.
+    capacity: usize,
+    /// How long entries live for, if they expire.
+    ttl: Option<Duration>,
+    /// Logical clock, ticked on every access, so recency doesn't need a system call.
+    clock: AtomicU64,
+}
+
+impl LruEvictionStrategy {
+    pub fn new(capacity: usize) -> Self {
+        LruEvictionStrategy {
+            capacity,
+            ttl: None,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Additionally evicts entries older than `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Ticks the logical clock.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl<K: Send + Sync + Hash + Eq + Clone> EvictionStrategy<K> for LruEvictionStrategy {
+    type Tag = UsageTag;
+
+    fn should_evict(&self, t: &Self::Tag) -> bool {
+        self.ttl.map_or(false, |ttl| ttl < t.created.elapsed())
+    }
+
+    fn create_tag(&self, _k: &K) -> Self::Tag {
+        UsageTag {
+            created: Instant::now(),
+            usage: Arc::new(AtomicU64::new(self.tick())),
+        }
+    }
+
+    fn on_access(&self, t: &Self::Tag) {
+        t.usage.store(self.tick(), Ordering::Relaxed);
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.capacity)
+    }
+
+    fn score(&self, t: &Self::Tag) -> u64 {
+        t.usage.load(Ordering::Relaxed)
+    }
+}
+
+/// Evicts the least frequently used entries once the cache is over capacity, and optionally entries older than a TTL.
+/// Replacing a value resets its count.
+#[derive(Copy, Clone, Debug)]
+pub struct LfuEvictionStrategy {
+    /// The most entries to keep.
+    capacity: usize,
+    /// How long entries live for, if they expire.
+    ttl: Option<Duration>,
+}
+
+impl LfuEvictionStrategy {
+    pub fn new(capacity: usize) -> Self {
+        LfuEvictionStrategy { capacity, ttl: None }
+    }
+
+    /// Additionally evicts entries older than `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+impl<K: Send + Sync + Hash + Eq + Clone> EvictionStrategy<K> for LfuEvictionStrategy {
+    type Tag = UsageTag;
+
+    fn should_evict(&self, t: &Self::Tag) -> bool {
+        self.ttl.map_or(false, |ttl| ttl < t.created.elapsed())
+    }
+
+    fn create_tag(&self, _k: &K) -> Self::Tag {
+        UsageTag {
+            created: Instant::now(),
+            usage: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn on_access(&self, t: &Self::Tag) {
+        t.usage.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(self.capacity)
+    }
+
+    fn score(&self, t: &Self::Tag) -> u64 {
+        t.usage.load(Ordering::Relaxed)
+    }
+}
+
+/// Running counts of how a cache has been used.
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A snapshot of a cache's size and usage.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CacheMetrics {
+    /// The number of keys in the cache, including any which have expired but haven't been swept yet.
+    pub size: usize,
+    /// Reads which found a live value.
+    pub hits: u64,
+    /// Reads which found nothing, or an expired value.
+    pub misses: u64,
+    /// Keys removed because they expired or the cache was over capacity.
+    pub evictions: u64,
+}
+
 #[derive(Debug)]
 pub struct Cache<
     K: Send + Sync + Hash + Eq + Clone,
@@ -101,6 +281,7 @@ pub struct Cache<
 > {
     cache: ArcSwap<im::HashMap<K, CacheValue<V, S::Tag>>>,
     strategy: S,
+    counters: Counters,
 }
 
 impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K> + Send + Sync> Cache<K, V, S> {
@@ -108,94 +289,293 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
         Self {
             cache: Default::default(),
             strategy,
+            counters: Default::default(),
         }
     }
 
-    pub fn ensure_entry<'a>(
-        &'a self,
-        k: &K,
-    ) -> impl Access<CacheValue<V, S::Tag>, Guard = impl Send + Deref<Target = CacheValue<V, S::Tag>>> + Send + 'a {
-        if self.cache.load().get(k).is_none() {
-            self.cache.rcu(|c| {
-                let mut c = im::HashMap::clone(c);
-                if !c.contains_key(k) {
-                    c.insert(k.clone(), CacheValue::default());
-                }
-                c
-            });
+    /// The number of keys in the cache, including any which have expired but haven't been swept yet.
+    pub fn len(&self) -> usize {
+        self.cache.load().len()
+    }
+
+    /// Whether the cache has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.cache.load().is_empty()
+    }
+
+    /// Takes a snapshot of the cache's size and usage.
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            size: self.len(),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
         }
-        let k = k.clone();
+    }
 
-        Map::new(&self.cache, move |c: &im::HashMap<K, CacheValue<V, S::Tag>>| {
-            c.get(&k).unwrap()
-        })
+    /// Counts a read which found a live value.
+    fn hit(&self, v: &Arc<(S::Tag, V)>) {
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        self.strategy.on_access(&v.0);
+    }
+
+    /// Counts a read which found nothing usable.
+    fn miss(&self) {
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Removes keys from the cache, counting them as evictions.
+    fn evict_keys(&self, keys: &[K]) -> usize {
+        if keys.is_empty() {
+            return 0;
+        }
+
+        let mut removed = 0;
+        self.cache.rcu(|c| {
+            let mut c = im::HashMap::clone(c);
+            removed = keys.iter().filter(|k| c.remove(*k).is_some()).count();
+            c
+        });
+        self.counters.evictions.fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// Whether a slot is being loaded. Such slots are never evicted, so the loaded value isn't lost.
+    fn is_loading(e: &CacheEntry<V, S::Tag>) -> bool {
+        e.loading.try_lock().is_err()
+    }
+
+    /// Whether a slot holds nothing worth keeping. Slots which are being loaded are always worth keeping.
+    fn is_dead(&self, e: &CacheEntry<V, S::Tag>) -> bool {
+        if Self::is_loading(e) {
+            return false;
+        }
+        match e.value.load_full() {
+            Some(v) => self.strategy.should_evict(&v.0),
+            None => true,
+        }
+    }
+
+    /// Whether a slot is still the one stored for a key, rather than having been evicted or removed.
+    fn is_published(&self, key: &K, e: &CacheValue<V, S::Tag>) -> bool {
+        self.cache.load().get(key).map_or(false, |cur| Arc::ptr_eq(cur, e))
     }
 
-    /// This is very subtly wrong
+    /// Evicts the lowest scoring keys if the cache is over capacity, never evicting `keep`.
+    /// To avoid doing this on every insert, it evicts down to 90% of capacity.
+    fn enforce_capacity(&self, keep: Option<&K>) -> usize {
+        let cap = match self.strategy.capacity() {
+            Some(c) => c,
+            None => return 0,
+        };
+        let cache = self.cache.load();
+        if cache.len() <= cap {
+            return 0;
+        }
+
+        let target = cap - cap / 10;
+        let mut candidates: Vec<(u64, &K)> = cache
+            .iter()
+            .filter(|(k, e)| Some(*k) != keep && !Self::is_loading(e))
+            .map(|(k, e)| match e.value.load_full() {
+                Some(v) if !self.strategy.should_evict(&v.0) => (self.strategy.score(&v.0), k),
+                _ => (0, k),
+            })
+            .collect();
+        candidates.sort_unstable_by_key(|(score, _)| *score);
+
+        let victims: Vec<K> = candidates
+            .into_iter()
+            .take(cache.len() - target)
+            .map(|(_, k)| k.clone())
+            .collect();
+        self.evict_keys(&victims)
+    }
+
+    /// Removes keys whose values have expired, then evicts keys until the cache is within capacity.
+    /// Returns the number of keys removed.
+    pub fn sweep(&self) -> usize {
+        let dead: Vec<K> = self
+            .cache
+            .load()
+            .iter()
+            .filter(|(_, e)| self.is_dead(e))
+            .map(|(k, _)| k.clone())
+            .collect();
+        self.evict_keys(&dead) + self.enforce_capacity(None)
+    }
+
+    /// Retrieves the slot for a key, creating an empty one if necessary.
+    /// Empty slots may be swept away at any time, so writes should go through [`Cache::insert`] or
+    /// [`Cache::update`] rather than straight to the slot.
+    pub fn ensure_entry(&self, k: &K) -> CacheValue<V, S::Tag> {
+        if let Some(e) = self.cache.load().get(k) {
+            return e.clone();
+        }
+
+        let mut out = None;
+        self.cache.rcu(|c| {
+            if let Some(e) = c.get(k) {
+                out = Some(e.clone());
+                return Arc::clone(c);
+            }
+            let e = CacheValue::default();
+            out = Some(e.clone());
+            Arc::new(c.update(k.clone(), e))
+        });
+        self.enforce_capacity(Some(k));
+        out.unwrap()
+    }
+
+    /// Like [`Cache::ensure_entry`], but a slot created for the key is published with its loading lock already
+    /// held, so it can't be swept away as empty before its loader starts.
+    fn ensure_entry_for_load(&self, k: &K) -> (CacheValue<V, S::Tag>, Option<OwnedMutexGuard<()>>) {
+        if let Some(e) = self.cache.load().get(k) {
+            return (e.clone(), None);
+        }
+
+        let mut out = None;
+        self.cache.rcu(|c| {
+            if let Some(e) = c.get(k) {
+                out = Some((e.clone(), None));
+                return Arc::clone(c);
+            }
+            let e = CacheValue::default();
+            let guard = e.loading.clone().try_lock_owned().expect("new slots aren't locked");
+            out = Some((e.clone(), Some(guard)));
+            Arc::new(c.update(k.clone(), e))
+        });
+        self.enforce_capacity(Some(k));
+        out.unwrap()
+    }
+
+    /// Discards a value which should be evicted.
+    fn live(&self, v: Option<Arc<(S::Tag, V)>>) -> Option<Arc<(S::Tag, V)>> {
+        v.filter(|a| !self.strategy.should_evict(&a.0))
+    }
+
+    /// Retrieves the value for a key, loading it with the given future if it's missing or should be evicted.
+    /// Only one loader runs per key at a time; concurrent callers wait for it and share its value rather than
+    /// running their own futures. If the loader fails, the next waiter tries its own future instead.
     pub async fn get_or_insert_with<Fut>(&self, key: &K, f: Fut) -> crate::error::Result<Cached<V, S::Tag>>
     where
         Fut: Future<Output = crate::error::Result<V>>,
     {
-        let cache = self.ensure_entry(key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
-        let cloaded = c.load_full();
-
-        let needs_reset = cloaded
-            .as_ref()
-            .map(|a| self.strategy.should_evict(&(*a).0))
-            .unwrap_or(true);
-
-        let out = if needs_reset {
-            let v = f.await?;
-            let ins = Arc::new((self.strategy.create_tag(key), v));
-            let mut out = ins.clone();
-            c.rcu(|r| {
-                if let Some(r) = r {
-                    out = r.clone();
-                    Some(r.clone())
-                } else {
-                    out = ins.clone();
-                    Some(ins.clone())
-                }
-            });
-            out
-        } else {
-            // The only way to get here is if `needs_reset` is false, which means
-            // the option was full.
-            cloaded.unwrap()
+        let (entry, _loading) = loop {
+            let (entry, guard) = self.ensure_entry_for_load(key);
+            if let Some(guard) = guard {
+                break (entry, guard);
+            }
+            if let Some(v) = self.live(entry.value.load_full()) {
+                self.hit(&v);
+                return Ok(Cached(v));
+            }
+
+            let guard = entry.loading.clone().lock_owned().await;
+            // An empty slot may have been swept away before we locked it; if so, start over with its replacement.
+            if self.is_published(key, &entry) {
+                break (entry, guard);
+            }
         };
+        // Another loader may have finished while we waited.
+        let before = entry.value.load_full();
+        if let Some(v) = self.live(before.clone()) {
+            self.hit(&v);
+            return Ok(Cached(v));
+        }
 
+        self.miss();
+        let ins = Arc::new((self.strategy.create_tag(key), f.await?));
+        // A live value inserted or updated while we were loading is newer than what we loaded, so keep it.
+        // Anything else, i.e. the evicted value we saw, or nothing because a reader dropped it, is replaced.
+        let mut out = ins.clone();
+        entry.value.rcu(|cur| match self.live(cur.clone()) {
+            Some(v) if !same_value(&Some(v.clone()), &before) => {
+                out = v.clone();
+                Some(v)
+            }
+            _ => {
+                out = ins.clone();
+                Some(ins.clone())
+            }
+        });
         Ok(Cached(out))
     }
 
+    /// Writes to the slot for a key, retrying on a fresh slot if the one written to was evicted meanwhile.
+    /// A slot created here is published with its loading lock held, so it can't be swept away as empty before
+    /// the write lands; an existing slot might be, in which case the write is redone.
+    fn write_slot<T>(&self, key: &K, mut write: impl FnMut(&CacheEntry<V, S::Tag>) -> T) -> T {
+        loop {
+            let (entry, _loading) = self.ensure_entry_for_load(key);
+            let out = write(&entry);
+            if self.is_published(key, &entry) {
+                return out;
+            }
+        }
+    }
+
     pub fn insert(&self, key: &K, v: V) {
-        self.ensure_entry(key)
-            .load()
-            .deref()
-            .store(Some(Arc::new((self.strategy.create_tag(key), v))));
+        let ins = Arc::new((self.strategy.create_tag(key), v));
+        self.write_slot(key, |e| e.value.store(Some(ins.clone())));
     }
 
     pub fn get(&self, key: &K) -> Option<Cached<V, S::Tag>> {
-        let cache = self.ensure_entry(&key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
-
-        let mut res = None;
-        c.rcu(|f| {
-            let needs_reset = f.as_ref().map(|a| self.strategy.should_evict(&(*a).0)).unwrap_or(true);
-            if needs_reset {
-                res = None;
+        let entry = match self.cache.load().get(key).cloned() {
+            Some(e) => e,
+            None => {
+                self.miss();
+                return None;
+            }
+        };
+        let cur = entry.value.load_full();
+        match self.live(cur.clone()) {
+            Some(v) => {
+                self.hit(&v);
+                Some(Cached(v))
+            }
+            None => {
+                self.miss();
+                // Drop the evicted value, unless it was replaced since we looked.
+                if cur.is_some() {
+                    entry.value.compare_and_swap(&cur, None);
+                }
                 None
-            } else {
-                res = f.clone();
-                f.clone()
             }
-        });
-
-        res.map(Cached)
+        }
     }
 
+    /// Retrieves the value for a key, inserting the given value if it's missing or should be evicted.
+    /// Unlike [`Cache::get_or_insert_with`], concurrent callers may each compute a value, but only one is kept.
     pub fn get_or_insert_sync(&self, key: &K, val: impl FnOnce() -> V) -> Cached<V, S::Tag> {
-        futures::executor::block_on(self.get_or_insert_with(key, async { Ok(val()) })).unwrap()
+        let mut val = Some(val);
+        let mut ins = None;
+        self.write_slot(key, |entry| {
+            if let Some(v) = self.live(entry.value.load_full()) {
+                self.hit(&v);
+                return Cached(v);
+            }
+
+            self.miss();
+            let ins = ins
+                .get_or_insert_with(|| {
+                    let val = val.take().expect("the value is only computed once");
+                    Arc::new((self.strategy.create_tag(key), val()))
+                })
+                .clone();
+            let mut out = ins.clone();
+            entry.value.rcu(|cur| match self.live(cur.clone()) {
+                Some(v) => {
+                    out = v.clone();
+                    Some(v)
+                }
+                None => {
+                    out = ins.clone();
+                    Some(ins.clone())
+                }
+            });
+            Cached(out)
+        })
     }
 
     pub fn get_or_insert_default(&self, key: &K) -> Cached<V, S::Tag>
@@ -212,6 +592,7 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
         self.insert(key, V::default())
     }
 
+    /// Drops the slot for a key. A load in progress for the key finishes without caching its value.
     pub fn remove(&self, key: &K) -> Option<Cached<V, S::Tag>> {
         let mut out = None;
         self.cache.rcu(|r| {
@@ -223,26 +604,39 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
                 r.clone()
             }
         });
-        out.and_then(|cv| cv.load_full()).map(Cached)
+        out.and_then(|cv| self.live(cv.value.load_full())).map(Cached)
     }
 
-    pub fn update(&self, key: &K, update_fn: impl Fn(Option<&V>) -> Option<V>) -> Update<V, S::Tag> {
-        let cache = self.ensure_entry(&key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
+    /// Drops every entry in the cache.
+    pub fn clear(&self) {
+        self.cache.store(Default::default());
+    }
 
-        let mut out = None;
-        c.rcu(|o| {
-            let needs_reset = o.as_ref().map(|a| self.strategy.should_evict(&(*a).0)).unwrap_or(true);
-            let pass_val = if needs_reset { None } else { o.clone() };
-            let new = update_fn(pass_val.as_ref().map(|c| &c.1));
-            let new = new.map(|v| Arc::new((self.strategy.create_tag(key), v)));
-            out = Some(Update {
-                old: pass_val.map(Cached),
-                new: new.clone().map(Cached),
+    /// Takes a snapshot of every live entry in the cache.
+    pub fn entries(&self) -> Vec<(K, Cached<V, S::Tag>)> {
+        self.cache
+            .load()
+            .iter()
+            .filter_map(|(k, v)| self.live(v.value.load_full()).map(|a| (k.clone(), Cached(a))))
+            .collect()
+    }
+
+    pub fn update(&self, key: &K, update_fn: impl Fn(Option<&V>) -> Option<V>) -> Update<V, S::Tag> {
+        self.write_slot(key, |entry| {
+            let mut out = None;
+            entry.value.rcu(|o| {
+                let needs_reset = o.as_ref().map(|a| self.strategy.should_evict(&a.0)).unwrap_or(true);
+                let pass_val = if needs_reset { None } else { o.clone() };
+                let new = update_fn(pass_val.as_ref().map(|c| &c.1));
+                let new = new.map(|v| Arc::new((self.strategy.create_tag(key), v)));
+                out = Some(Update {
+                    old: pass_val.map(Cached),
+                    new: new.clone().map(Cached),
+                });
+                new
             });
-            new
-        });
-        out.unwrap()
+            out.unwrap()
+        })
     }
 
     pub fn update_and_fetch(&self, key: &K, update_fn: impl Fn(Option<&V>) -> Option<V>) -> Option<Cached<V, S::Tag>> {
@@ -1,24 +1,21 @@
 //! Contains abstractions over the persistent store connections for glimbot.
-//! Currently, glimbot relies on a PostgreSQL server for its persistent store.
+//! Glimbot stores its state in PostgreSQL or SQLite, through the backends in [`storage`].
 
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::future::Future;
 use std::io;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use once_cell::sync::Lazy;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serenity::model::id::GuildId;
-use sqlx::migrate::Migrator;
-use sqlx::postgres::PgConnectOptions;
-use sqlx::PgPool;
+use serenity::model::id::{GuildId, UserId};
 
 use crate::db::cache::{Cache, NullEvictionStrategy};
+use crate::db::storage::Storage;
 
 use crate::dispatch::Dispatch;
 
@@ -27,6 +24,8 @@ use downcast_rs::DowncastSync;
 use futures::TryFutureExt;
 use std::any::Any;
 
+pub mod notify;
+pub mod storage;
 pub mod timed;
 #[macro_use]
 pub mod cache;
@@ -58,30 +57,15 @@ pub fn ensure_data_folder() -> io::Result<PathBuf> {
     Ok(dir)
 }
 
-/// The SQL migrations to be automatically applied on startup.
-static MIGRATIONS: Migrator = sqlx::migrate!();
-
-/// Create the database connection pool. This will eagerly spawn a single connection,
-/// and spawn more as contention occurs.
-pub async fn create_pool() -> crate::error::Result<PgPool> {
-    let db_url = std::env::var("DATABASE_URL")?;
-
-    let pool = sqlx::PgPool::connect_with(PgConnectOptions::from_str(&db_url)?.application_name("glimbot")).await?;
-
-    info!("Running DB migrations if necessary.");
-    MIGRATIONS.run(&pool).await?;
-    Ok(pool)
-}
-
-/// A thin wrapper around a DB pool and the guild which queries should target.
+/// A thin wrapper around the storage backend and the guild which queries should target.
 #[derive(Clone)]
 pub struct DbContext<'pool> {
     /// The guild that queries will target.
     guild: GuildId,
-    /// A reference to the connection pool. We don't take a connection because we can usually
-    /// significantly reduce contention on the connections by only holding one for the duration
-    /// of the query.
+    /// The dispatch whose storage and config cache are used.
     conn: &'pool Dispatch,
+    /// The user on whose behalf config changes are made, recorded in the config history.
+    actor: Option<UserId>,
 }
 
 impl DbContext<'_> {
@@ -94,12 +78,11 @@ impl DbContext<'_> {
     pub fn guild_as_i64(&self) -> i64 {
         self.guild.0 as i64
     }
-}
 
-#[doc(hidden)]
-#[derive(Debug)]
-struct ConfigRow {
-    value: serde_json::Value,
+    /// Gets the user on whose behalf config changes are made, if any.
+    pub fn actor(&self) -> Option<UserId> {
+        self.actor
+    }
 }
 
 /// The actual contents of a cache member
@@ -194,6 +177,33 @@ impl ConfigCache {
         Ok(())
     }
 
+    /// Drops a value from the guild cache, so the next read goes to the DB.
+    pub fn remove<K: ConfigKey>(&self, gid: GuildId, key: K) {
+        self.inc_access();
+        self.cache
+            .get(key.to_key().as_ref())
+            .expect("Unexpected config key")
+            .remove(&gid);
+    }
+
+    /// Drops a value from the guild cache if the key is known. Unlike [`ConfigCache::remove`], this accepts keys
+    /// from outside Glimbot, such as config change notifications, so unknown keys are ignored.
+    pub fn evict(&self, gid: GuildId, key: &str) {
+        if let Some(c) = self.cache.get(key) {
+            c.remove(&gid);
+        }
+    }
+
+    /// Removes empty entries left behind by failed loads. Config values never expire on their own.
+    pub fn sweep(&self) -> usize {
+        self.cache.values().map(Cache::sweep).sum()
+    }
+
+    /// Drops every cached value for every guild.
+    pub fn clear(&self) {
+        self.cache.values().for_each(Cache::clear);
+    }
+
     /// Retrieves a value (which may not be set) from the given future or the cache.
     pub async fn get<K, Fut, R>(&self, gid: GuildId, key: K, f: Fut) -> crate::error::Result<Option<Arc<R>>>
     where
@@ -220,17 +230,32 @@ impl ConfigCache {
     }
 }
 
-impl DbContext<'_> {
-    /// Retrieves a reference to the underlying connection pool.
-    pub fn conn(&self) -> &PgPool {
-        self.conn.pool()
+impl<'pool> DbContext<'pool> {
+    /// Retrieves a reference to the underlying storage backend.
+    pub fn storage(&self) -> &dyn Storage {
+        self.conn.storage()
+    }
+
+    /// Retrieves the dispatch this context belongs to.
+    pub fn dispatch(&self) -> &'pool Dispatch {
+        self.conn
     }
 }
 
 impl<'pool> DbContext<'pool> {
     /// Creates a guild-focused context wrapping around a connection pool.
     pub fn new<'b: 'pool>(pool: &'b Dispatch, guild: GuildId) -> Self {
-        Self { guild, conn: pool }
+        Self {
+            guild,
+            conn: pool,
+            actor: None,
+        }
+    }
+
+    /// Attributes config changes made through this context to a user in the config history.
+    pub fn with_actor(mut self, user: UserId) -> Self {
+        self.actor = Some(user);
+        self
     }
 
     /// Retrieves or inserts a value for the guild config.
@@ -259,19 +284,11 @@ impl<'pool> DbContext<'pool> {
         F: (Fn() -> S) + Send + Sync,
     {
         let v = serde_json::to_value(def())?;
-        let key = key.to_key();
-
-        let out: Option<serde_json::Value> = sqlx::query_scalar!(
-            r#"
-                SELECT res AS value FROM get_or_insert_config($1, $2, $3);
-                "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-            v
-        )
-        .fetch_one(self.conn())
-        .await?;
-        Ok(serde_json::from_value(out.expect("Failed to submit value to DB?"))?)
+        let out = self
+            .storage()
+            .get_or_insert_config(self.guild, key.to_key().as_ref(), v)
+            .await?;
+        Ok(serde_json::from_value(out)?)
     }
 
     /// Inserts a value into the guild config. This version will hit the cache in addition to the database.
@@ -288,31 +305,43 @@ impl<'pool> DbContext<'pool> {
     }
 
     /// Inserts a value into the guild config, and will bypass the cache. This should be avoided to avoid stale reads from the cache.
+    /// The change is recorded in the config history along with the previous value.
     async fn insert_uncached<B, S>(&self, key: B, val: S) -> crate::error::Result<S>
     where
         B: ConfigKey,
         S: Serialize + Clone + Sized,
     {
-        let key = key.to_key();
         let v = serde_json::to_value(&val)?;
-
-        sqlx::query!(
-            r#"
-            INSERT INTO config_values (guild, name, value)
-            VALUES ($1, $2, $3)
-            ON CONFLICT (guild, name) DO UPDATE
-                SET value = EXCLUDED.value;
-            "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-            &v
-        )
-        .execute(self.conn())
-        .await
-        .map(|_| ())?;
+        self.storage()
+            .set_config(self.guild, key.to_key().as_ref(), &v, self.actor)
+            .await?;
         Ok(val)
     }
 
+    /// Removes a value from the guild config, recording the change in the config history and dropping it from the cache.
+    #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn remove<B: ConfigKey>(&self, key: B) -> crate::error::Result<()> {
+        let key = key.to_key();
+        self.storage()
+            .remove_config(self.guild, key.as_ref(), self.actor)
+            .await?;
+        self.conn.config_cache().remove(self.guild, key);
+        Ok(())
+    }
+
+    /// Replaces a config value in the cache without touching the database, i.e. after it was written with
+    /// [`Storage::import_config`].
+    pub async fn cache_insert<B, S>(&self, key: B, val: S) -> crate::error::Result<()>
+    where
+        B: ConfigKey,
+        S: Cacheable + Clone + Sized,
+    {
+        self.conn
+            .config_cache()
+            .insert_with(self.guild, key.to_key(), async { Ok(val) })
+            .await
+    }
+
     /// Hits the cache to retrieve a config value, hitting the DB if necessary.
     #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
     pub async fn get<B, D>(&self, key: B) -> crate::error::Result<Option<Arc<D>>>
@@ -332,29 +361,19 @@ impl<'pool> DbContext<'pool> {
         B: ConfigKey,
         D: DeserializeOwned,
     {
-        let key = key.to_key();
-        let o: Option<ConfigRow> = sqlx::query_as!(
-            ConfigRow,
-            r#"
-            SELECT value FROM config_values WHERE guild = $1 AND name = $2;
-            "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-        )
-        .fetch_optional(self.conn())
-        .await?;
-        Ok(o.map(|c| c.value).map(serde_json::from_value).transpose()?)
+        let o = self.storage().get_config(self.guild, key.to_key().as_ref()).await?;
+        Ok(o.map(serde_json::from_value).transpose()?)
     }
 }
 
 /// Trait for configuration keys to implement.
 pub trait ConfigKey {
     /// Should return this key as a view on a string.
-    fn to_key(&self) -> Cow<str>;
+    fn to_key(&self) -> Cow<'_, str>;
 }
 
 impl<T: AsRef<str>> ConfigKey for T {
-    fn to_key(&self) -> Cow<str> {
+    fn to_key(&self) -> Cow<'_, str> {
         self.as_ref().into()
     }
 }
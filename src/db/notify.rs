@@ -0,0 +1,92 @@
+//! Contains the listener which keeps the config cache coherent across processes sharing a PostgreSQL database.
+//! Every write to `config_values`, whether from Glimbot or from SQL, triggers a notification on
+//! [`CONFIG_CHANNEL`]; each running process evicts the changed value so its next read goes to the DB.
+
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use serenity::model::id::GuildId;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+
+use crate::dispatch::Dispatch;
+
+/// The channel config change notifications are sent on.
+pub const CONFIG_CHANNEL: &str = "config_changed";
+/// How long to wait before reconnecting after the listener fails.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The payload of a config change notification.
+#[derive(Debug, Deserialize)]
+struct ConfigChanged {
+    /// The guild whose config changed.
+    guild: i64,
+    /// The config key that changed.
+    name: String,
+}
+
+/// Listens for config change notifications and evicts the changed values from the config cache.
+pub struct ConfigListener {
+    /// The connection notifications arrive on.
+    listener: PgListener,
+    /// The dispatch whose cache should be kept up to date. Weak so the listener stops once it's dropped.
+    dispatch: Weak<Dispatch>,
+}
+
+impl ConfigListener {
+    /// Connects to the database and subscribes to config change notifications.
+    pub async fn connect(dispatch: &Arc<Dispatch>, pool: &PgPool) -> crate::error::Result<Self> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(CONFIG_CHANNEL).await?;
+        Ok(ConfigListener {
+            listener,
+            dispatch: Arc::downgrade(dispatch),
+        })
+    }
+
+    /// Evicts changed values until the dispatch is dropped.
+    /// Notifications sent while the connection is down are lost, so the whole cache is cleared whenever it drops.
+    pub async fn run(mut self) {
+        loop {
+            let res = self.listener.try_recv().await;
+            let dis = match self.dispatch.upgrade() {
+                Some(d) => d,
+                None => return,
+            };
+
+            match res {
+                Ok(Some(n)) => Self::evict(&dis, n.payload()),
+                Ok(None) => {
+                    warn!("Lost connection to config change notifications; clearing the config cache.");
+                    dis.config_cache().clear();
+                }
+                Err(e) => {
+                    error!("Failed while listening for config changes: {}", e);
+                    dis.config_cache().clear();
+                    std::mem::drop(dis); // Avoid holding the dispatch while we wait.
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Evicts the value named in a notification payload, or everything if the payload is empty.
+    fn evict(dis: &Dispatch, payload: &str) {
+        if payload.is_empty() {
+            debug!("config values truncated; clearing the config cache");
+            dis.config_cache().clear();
+            return;
+        }
+
+        match serde_json::from_str::<ConfigChanged>(payload) {
+            Ok(c) => {
+                trace!("evicting {} for guild {}", c.name, c.guild);
+                dis.config_cache().evict(GuildId(c.guild as u64), &c.name);
+            }
+            Err(e) => {
+                warn!("Got a malformed config change notification {:?}: {}", payload, e);
+                dis.config_cache().clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Contains an in-memory storage backend, which lets glimbot run without a database, i.e. in tests.
+//! Nothing is persisted; all state is lost when the storage is dropped.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::Utc;
+use parking_lot::Mutex;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::db::storage::{
+    AlreadyJoinable, ConfigChange, DuplicateAction, RiskRecord, Storage, StoredPressure, TooManyRoles,
+    MAX_JOINABLE_ROLES,
+};
+use crate::db::timed::Action;
+
+/// Stores glimbot's state in memory. Every operation holds a single lock, so each one is atomic.
+#[derive(Default)]
+pub struct MemoryStorage {
+    /// The stored state.
+    state: Mutex<MemoryState>,
+}
+
+/// Everything a [`MemoryStorage`] holds, laid out like the database tables.
+#[derive(Default)]
+struct MemoryState {
+    /// Config values, keyed by guild and name.
+    config: HashMap<(GuildId, String), serde_json::Value>,
+    /// The changes to each config value, oldest first.
+    history: HashMap<(GuildId, String), Vec<ConfigChange>>,
+    /// The joinable roles in each guild.
+    joinable: HashMap<GuildId, BTreeSet<RoleId>>,
+    /// Pending timed actions.
+    timed: Vec<Action>,
+    /// The sticky roles of users who left each guild.
+    sticky: HashMap<(GuildId, UserId), BTreeSet<RoleId>>,
+    /// Member risk records.
+    risk: HashMap<(GuildId, UserId), RiskRecord>,
+    /// Persisted spam pressure in each guild.
+    pressure: HashMap<GuildId, Vec<StoredPressure>>,
+}
+
+impl MemoryState {
+    /// Sets or unsets a config value, recording the change in the config history if anything changed.
+    fn write_config(&mut self, guild: GuildId, key: &str, v: Option<&serde_json::Value>, actor: Option<UserId>) {
+        let k = (guild, key.to_string());
+        let old = match v {
+            Some(v) => self.config.insert(k.clone(), v.clone()),
+            None => self.config.remove(&k),
+        };
+        if old.is_none() && v.is_none() {
+            return;
+        }
+
+        let history = self.history.entry(k).or_default();
+        history.push(ConfigChange {
+            version: history.len() as i32 + 1,
+            old_value: old,
+            new_value: v.cloned(),
+            changed_by: actor,
+            changed_at: Utc::now(),
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for MemoryStorage {
+    async fn get_config(&self, guild: GuildId, key: &str) -> crate::error::Result<Option<serde_json::Value>> {
+        Ok(self.state.lock().config.get(&(guild, key.to_string())).cloned())
+    }
+
+    async fn get_or_insert_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        default: serde_json::Value,
+    ) -> crate::error::Result<serde_json::Value> {
+        let mut state = self.state.lock();
+        Ok(state.config.entry((guild, key.to_string())).or_insert(default).clone())
+    }
+
+    async fn set_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        self.state.lock().write_config(guild, key, Some(value), actor);
+        Ok(())
+    }
+
+    async fn remove_config(&self, guild: GuildId, key: &str, actor: Option<UserId>) -> crate::error::Result<()> {
+        self.state.lock().write_config(guild, key, None, actor);
+        Ok(())
+    }
+
+    async fn import_config(
+        &self,
+        guild: GuildId,
+        values: &[(String, serde_json::Value)],
+        joinable_roles: Option<&[RoleId]>,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        let roles: Option<BTreeSet<RoleId>> = joinable_roles.map(|r| r.iter().copied().collect());
+        if roles.as_ref().map_or(false, |r| r.len() as i64 > MAX_JOINABLE_ROLES) {
+            return Err(TooManyRoles.into());
+        }
+
+        let mut state = self.state.lock();
+        for (key, value) in values {
+            state.write_config(guild, key, Some(value), actor);
+        }
+        if let Some(roles) = roles {
+            state.joinable.insert(guild, roles);
+        }
+        Ok(())
+    }
+
+    async fn config_history(&self, guild: GuildId, key: &str, limit: i64) -> crate::error::Result<Vec<ConfigChange>> {
+        let state = self.state.lock();
+        Ok(state
+            .history
+            .get(&(guild, key.to_string()))
+            .map(|h| h.iter().rev().take(limit.max(0) as usize).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn config_version(
+        &self,
+        guild: GuildId,
+        key: &str,
+        version: i32,
+    ) -> crate::error::Result<Option<ConfigChange>> {
+        let state = self.state.lock();
+        Ok(state
+            .history
+            .get(&(guild, key.to_string()))
+            .and_then(|h| h.iter().find(|c| c.version == version))
+            .cloned())
+    }
+
+    async fn add_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        let mut state = self.state.lock();
+        let roles = state.joinable.entry(guild).or_default();
+        if roles.contains(&role) {
+            return Err(AlreadyJoinable.into());
+        }
+        if roles.len() as i64 >= MAX_JOINABLE_ROLES {
+            return Err(TooManyRoles.into());
+        }
+        roles.insert(role);
+        Ok(())
+    }
+
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        if let Some(roles) = self.state.lock().joinable.get_mut(&guild) {
+            roles.remove(&role);
+        }
+        Ok(())
+    }
+
+    async fn is_joinable(&self, guild: GuildId, role: RoleId) -> crate::error::Result<bool> {
+        Ok(self
+            .state
+            .lock()
+            .joinable
+            .get(&guild)
+            .map_or(false, |r| r.contains(&role)))
+    }
+
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<RoleId>> {
+        Ok(self
+            .state
+            .lock()
+            .joinable
+            .get(&guild)
+            .map(|r| r.iter().copied().collect())
+            .unwrap_or_default())
+    }
+
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
+        let mut state = self.state.lock();
+        // Mirrors the unique_timed_event constraint in the SQL backends.
+        if state.timed.contains(action) {
+            return Err(DuplicateAction.into());
+        }
+        state.timed.push(*action);
+        Ok(())
+    }
+
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
+        self.state.lock().timed.retain(|a| a != action);
+        Ok(())
+    }
+
+    async fn actions_for_user(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<Action>> {
+        let mut out: Vec<Action> = self
+            .state
+            .lock()
+            .timed
+            .iter()
+            .filter(|a| a.guild() == guild && a.target_user() == user)
+            .copied()
+            .collect();
+        out.sort_by_key(Action::expiry);
+        Ok(out)
+    }
+
+    async fn actions_before(&self, epoch: chrono::DateTime<Utc>, limit: usize) -> crate::error::Result<Vec<Action>> {
+        let mut out: Vec<Action> = self
+            .state
+            .lock()
+            .timed
+            .iter()
+            .filter(|a| a.expiry() <= epoch)
+            .copied()
+            .collect();
+        out.sort_by_key(Action::expiry);
+        out.truncate(limit);
+        Ok(out)
+    }
+
+    async fn store_sticky_roles(&self, guild: GuildId, user: UserId, roles: &[RoleId]) -> crate::error::Result<()> {
+        self.state
+            .lock()
+            .sticky
+            .entry((guild, user))
+            .or_default()
+            .extend(roles.iter().copied());
+        Ok(())
+    }
+
+    async fn take_sticky_roles(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<RoleId>> {
+        let roles = self.state.lock().sticky.remove(&(guild, user));
+        Ok(roles.map(|r| r.into_iter().collect()).unwrap_or_default())
+    }
+
+    async fn drop_sticky_role(&self, guild: GuildId, user: UserId, role: RoleId) -> crate::error::Result<()> {
+        if let Some(roles) = self.state.lock().sticky.get_mut(&(guild, user)) {
+            roles.remove(&role);
+        }
+        Ok(())
+    }
+
+    async fn risk_record(&self, guild: GuildId, user: UserId) -> crate::error::Result<Option<RiskRecord>> {
+        Ok(self.state.lock().risk.get(&(guild, user)).copied())
+    }
+
+    async fn record_risk_message(&self, guild: GuildId, user: UserId, limit: i32) -> crate::error::Result<()> {
+        let mut state = self.state.lock();
+        let r = state.risk.entry((guild, user)).or_default();
+        if r.messages < limit {
+            r.messages += 1;
+        }
+        Ok(())
+    }
+
+    async fn record_risk_action(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        warns: i32,
+        mutes: i32,
+    ) -> crate::error::Result<()> {
+        let mut state = self.state.lock();
+        let r = state.risk.entry((guild, user)).or_default();
+        r.warns += warns;
+        r.mutes += mutes;
+        Ok(())
+    }
+
+    async fn record_peak_pressures(&self, guild: GuildId, pressures: &[(UserId, f64)]) -> crate::error::Result<()> {
+        let mut state = self.state.lock();
+        for (user, pressure) in pressures {
+            let r = state.risk.entry((guild, *user)).or_default();
+            r.peak_pressure = r.peak_pressure.max(*pressure);
+        }
+        Ok(())
+    }
+
+    async fn load_spam_pressure(&self, guild: GuildId) -> crate::error::Result<Vec<StoredPressure>> {
+        Ok(self.state.lock().pressure.get(&guild).cloned().unwrap_or_default())
+    }
+
+    async fn replace_spam_pressure(&self, guild: GuildId, pressures: &[StoredPressure]) -> crate::error::Result<()> {
+        self.state.lock().pressure.insert(guild, pressures.to_vec());
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! Contains the storage trait glimbot's persistent state goes through, along with the backends implementing it.
+//! The backend is chosen by the scheme of `DATABASE_URL`: `postgres://` selects [`PostgresStorage`], and
+//! `sqlite:` selects [`SqliteStorage`]. [`MemoryStorage`] keeps everything in memory, for tests.
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use downcast_rs::impl_downcast;
+use downcast_rs::DowncastSync;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::db::timed::Action;
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+/// The most roles a guild may make joinable.
+pub const MAX_JOINABLE_ROLES: i64 = 128;
+
+impl_err!(
+    UnsupportedDatabase,
+    "DATABASE_URL must start with postgres://, postgresql:// or sqlite:.",
+    false
+);
+
+impl_err!(
+    TooManyRoles,
+    "Can't add more roles to joinable; you have too many!",
+    true
+);
+impl_err!(AlreadyJoinable, "This role is already joinable.", true);
+impl_err!(DuplicateAction, "An identical timed action is already stored.", false);
+
+/// Persisted spam pressure for a user, along with when it was last updated.
+pub type StoredPressure = (UserId, f64, chrono::DateTime<Utc>);
+
+/// A single recorded change to a config value.
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    /// The version of the config value this change produced, counting from 1.
+    pub version: i32,
+    /// The value before the change, if it was set.
+    pub old_value: Option<serde_json::Value>,
+    /// The value after the change, if it's still set.
+    pub new_value: Option<serde_json::Value>,
+    /// Who made the change, if it was made by a user.
+    pub changed_by: Option<UserId>,
+    /// When the change was made.
+    pub changed_at: chrono::DateTime<Utc>,
+}
+
+/// What Glimbot remembers about a member for risk scoring.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RiskRecord {
+    /// Messages seen from the member, up to the limit they're counted to.
+    pub messages: i32,
+    /// Warnings the member has received.
+    pub warns: i32,
+    /// Mutes the member has received.
+    pub mutes: i32,
+    /// The highest spam pressure the member has been seen with.
+    pub peak_pressure: f64,
+}
+
+/// A persistent store for glimbot's per-guild state.
+/// Config writes are recorded in the config history along with the user who made them, if any.
+#[async_trait::async_trait]
+pub trait Storage: Send + Sync + DowncastSync {
+    /// Retrieves a config value, if it's set.
+    async fn get_config(&self, guild: GuildId, key: &str) -> crate::error::Result<Option<serde_json::Value>>;
+
+    /// Retrieves a config value, setting it to `default` first if it isn't set.
+    async fn get_or_insert_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        default: serde_json::Value,
+    ) -> crate::error::Result<serde_json::Value>;
+
+    /// Sets a config value.
+    async fn set_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()>;
+
+    /// Unsets a config value.
+    async fn remove_config(&self, guild: GuildId, key: &str, actor: Option<UserId>) -> crate::error::Result<()>;
+
+    /// Sets several config values and, if given, replaces the joinable roles, all at once.
+    async fn import_config(
+        &self,
+        guild: GuildId,
+        values: &[(String, serde_json::Value)],
+        joinable_roles: Option<&[RoleId]>,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()>;
+
+    /// Retrieves the most recent changes to a config value, newest first.
+    async fn config_history(&self, guild: GuildId, key: &str, limit: i64) -> crate::error::Result<Vec<ConfigChange>>;
+
+    /// Retrieves a specific version of a config value, if it exists.
+    async fn config_version(
+        &self,
+        guild: GuildId,
+        key: &str,
+        version: i32,
+    ) -> crate::error::Result<Option<ConfigChange>>;
+
+    /// Makes a role joinable. Fails if the guild has too many joinable roles or the role is already joinable.
+    async fn add_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()>;
+
+    /// Removes a role from the joinable list.
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()>;
+
+    /// Returns whether or not the role is joinable.
+    async fn is_joinable(&self, guild: GuildId, role: RoleId) -> crate::error::Result<bool>;
+
+    /// Retrieves the joinable roles, in ascending order.
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<RoleId>>;
+
+    /// Stores a timed action.
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()>;
+
+    /// Deletes a timed action.
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()>;
+
+    /// Retrieves the pending actions against a user in the guild, soonest first.
+    async fn actions_for_user(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<Action>>;
+
+    /// Retrieves up to `limit` actions in any guild which expire at or before `epoch`, soonest first.
+    async fn actions_before(&self, epoch: chrono::DateTime<Utc>, limit: usize) -> crate::error::Result<Vec<Action>>;
+
+    /// Stores roles which should be re-applied if the user rejoins.
+    async fn store_sticky_roles(&self, guild: GuildId, user: UserId, roles: &[RoleId]) -> crate::error::Result<()>;
+
+    /// Removes and returns all sticky roles stored for a user.
+    async fn take_sticky_roles(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<RoleId>>;
+
+    /// Removes a single sticky role stored for a user.
+    async fn drop_sticky_role(&self, guild: GuildId, user: UserId, role: RoleId) -> crate::error::Result<()>;
+
+    /// Retrieves the risk record for a user, if there is one.
+    async fn risk_record(&self, guild: GuildId, user: UserId) -> crate::error::Result<Option<RiskRecord>>;
+
+    /// Counts a message from a user, up to `limit`.
+    async fn record_risk_message(&self, guild: GuildId, user: UserId, limit: i32) -> crate::error::Result<()>;
+
+    /// Adds warnings and mutes to a user's risk record.
+    async fn record_risk_action(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        warns: i32,
+        mutes: i32,
+    ) -> crate::error::Result<()>;
+
+    /// Raises the peak pressure of users, if the given pressure is higher than what was stored.
+    async fn record_peak_pressures(&self, guild: GuildId, pressures: &[(UserId, f64)]) -> crate::error::Result<()>;
+
+    /// Loads all persisted spam pressure for the guild.
+    async fn load_spam_pressure(&self, guild: GuildId) -> crate::error::Result<Vec<StoredPressure>>;
+
+    /// Replaces all persisted spam pressure for the guild.
+    async fn replace_spam_pressure(&self, guild: GuildId, pressures: &[StoredPressure]) -> crate::error::Result<()>;
+}
+impl_downcast!(sync Storage);
+
+/// Connects to the storage backend named by `DATABASE_URL`, running its migrations if necessary.
+pub async fn connect_storage() -> crate::error::Result<Arc<dyn Storage>> {
+    let db_url = std::env::var("DATABASE_URL")?;
+    let storage: Arc<dyn Storage> = if db_url.starts_with("postgres://") || db_url.starts_with("postgresql://") {
+        Arc::new(PostgresStorage::connect(&db_url).await?)
+    } else if db_url.starts_with("sqlite:") {
+        Arc::new(SqliteStorage::connect(&db_url).await?)
+    } else {
+        return Err(UnsupportedDatabase.into());
+    };
+    Ok(storage)
+}
@@ -0,0 +1,560 @@
+//! Contains the PostgreSQL storage backend, which is the one glimbot has always used.
+//! Config changes made here are announced to other processes; see [`crate::db::notify`].
+
+use std::str::FromStr;
+
+use chrono::Utc;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::db::storage::{AlreadyJoinable, ConfigChange, RiskRecord, Storage, StoredPressure, TooManyRoles};
+use crate::db::timed::Action;
+use crate::error::DatabaseError;
+
+/// The SQL migrations to be automatically applied on startup.
+static MIGRATIONS: Migrator = sqlx::migrate!();
+
+/// Stores glimbot's state in a PostgreSQL database.
+pub struct PostgresStorage {
+    /// The connection pool. We don't hold a connection because we can usually significantly reduce
+    /// contention on the connections by only holding one for the duration of the query.
+    pool: PgPool,
+}
+
+#[doc(hidden)]
+struct TimedRow {
+    target_user: i64,
+    guild: i64,
+    expiry: chrono::DateTime<Utc>,
+    action: serde_json::Value,
+}
+
+impl TimedRow {
+    /// Converts a row into an action.
+    fn into_action(self) -> Result<Action, sqlx::Error> {
+        Ok(Action::new(
+            (self.target_user as u64).into(),
+            (self.guild as u64).into(),
+            serde_json::from_value(self.action).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            self.expiry,
+        ))
+    }
+}
+
+impl PostgresStorage {
+    /// Creates the database connection pool and runs migrations if necessary. This will eagerly spawn a single
+    /// connection, and spawn more as contention occurs.
+    pub async fn connect(db_url: &str) -> crate::error::Result<Self> {
+        let pool = PgPool::connect_with(PgConnectOptions::from_str(db_url)?.application_name("glimbot")).await?;
+
+        info!("Running DB migrations if necessary.");
+        MIGRATIONS.run(&pool).await?;
+        Ok(Self::from_pool(pool))
+    }
+
+    /// Wraps an existing connection pool, which must already be migrated.
+    pub fn from_pool(pool: PgPool) -> Self {
+        PostgresStorage { pool }
+    }
+
+    /// Retrieves a reference to the underlying connection pool.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Serialises changes to a config key until the end of the transaction. The row in `config_values` can't be
+    /// locked for this, as it doesn't exist before the key is first set, and without it concurrent changes would
+    /// read the same old value and history version.
+    async fn lock_config_key(
+        tx: &mut Transaction<'_, Postgres>,
+        guild: GuildId,
+        key: &str,
+    ) -> crate::error::Result<()> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($2, $1));")
+            .bind(guild.0 as i64)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Writes a config value and records the change in the config history as part of a larger transaction.
+    async fn write_config(
+        tx: &mut Transaction<'_, Postgres>,
+        guild: GuildId,
+        key: &str,
+        v: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        Self::lock_config_key(tx, guild, key).await?;
+        let old: Option<serde_json::Value> = sqlx::query_scalar!(
+            "SELECT value FROM config_values WHERE guild = $1 AND name = $2 FOR UPDATE;",
+            guild.0 as i64,
+            key
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO config_values (guild, name, value)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (guild, name) DO UPDATE
+                SET value = EXCLUDED.value;
+            "#,
+            guild.0 as i64,
+            key,
+            v
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Self::record_config_change(tx, guild, key, old, Some(v), actor).await
+    }
+
+    /// Appends a change to the config history as part of a larger transaction.
+    async fn record_config_change(
+        tx: &mut Transaction<'_, Postgres>,
+        guild: GuildId,
+        key: &str,
+        old: Option<serde_json::Value>,
+        new: Option<&serde_json::Value>,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO config_history (guild, name, version, old_value, new_value, changed_by)
+            SELECT $1, $2, COALESCE(MAX(version), 0) + 1, $3, $4, $5
+            FROM config_history WHERE guild = $1 AND name = $2;
+            "#,
+            guild.0 as i64,
+            key,
+            old,
+            new,
+            actor.map(|u| u.0 as i64)
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the guild's joinable roles as part of a larger transaction.
+    async fn replace_joinable_roles(
+        tx: &mut Transaction<'_, Postgres>,
+        guild: GuildId,
+        roles: &[RoleId],
+    ) -> crate::error::Result<()> {
+        let roles: Vec<i64> = roles.iter().map(|r| r.0 as i64).collect();
+        sqlx::query!("DELETE FROM joinable_roles WHERE guild = $1;", guild.0 as i64)
+            .execute(&mut *tx)
+            .await?;
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO joinable_roles (guild, role) SELECT $1, UNNEST($2::BIGINT[]);",
+            guild.0 as i64,
+            &roles
+        )
+        .execute(&mut *tx)
+        .await;
+
+        match res {
+            Err(e) if e.is_check() => Err(TooManyRoles.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for PostgresStorage {
+    async fn get_config(&self, guild: GuildId, key: &str) -> crate::error::Result<Option<serde_json::Value>> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT value FROM config_values WHERE guild = $1 AND name = $2;
+            "#,
+            guild.0 as i64,
+            key,
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn get_or_insert_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        default: serde_json::Value,
+    ) -> crate::error::Result<serde_json::Value> {
+        let out: Option<serde_json::Value> = sqlx::query_scalar!(
+            r#"
+                SELECT res AS value FROM get_or_insert_config($1, $2, $3);
+                "#,
+            guild.0 as i64,
+            key,
+            default
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(out.expect("Failed to submit value to DB?"))
+    }
+
+    async fn set_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::write_config(&mut tx, guild, key, value, actor).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_config(&self, guild: GuildId, key: &str, actor: Option<UserId>) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_config_key(&mut tx, guild, key).await?;
+        let old: Option<serde_json::Value> = sqlx::query_scalar!(
+            "DELETE FROM config_values WHERE guild = $1 AND name = $2 RETURNING value;",
+            guild.0 as i64,
+            key
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        if old.is_some() {
+            Self::record_config_change(&mut tx, guild, key, old, None, actor).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn import_config(
+        &self,
+        guild: GuildId,
+        values: &[(String, serde_json::Value)],
+        joinable_roles: Option<&[RoleId]>,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (key, value) in values {
+            Self::write_config(&mut tx, guild, key, value, actor).await?;
+        }
+        if let Some(roles) = joinable_roles {
+            Self::replace_joinable_roles(&mut tx, guild, roles).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn config_history(&self, guild: GuildId, key: &str, limit: i64) -> crate::error::Result<Vec<ConfigChange>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT version, old_value, new_value, changed_by, changed_at FROM config_history
+            WHERE guild = $1 AND name = $2
+            ORDER BY version DESC LIMIT $3;
+            "#,
+            guild.0 as i64,
+            key,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ConfigChange {
+                version: r.version,
+                old_value: r.old_value,
+                new_value: r.new_value,
+                changed_by: r.changed_by.map(|u| UserId::from(u as u64)),
+                changed_at: r.changed_at,
+            })
+            .collect())
+    }
+
+    async fn config_version(
+        &self,
+        guild: GuildId,
+        key: &str,
+        version: i32,
+    ) -> crate::error::Result<Option<ConfigChange>> {
+        let r = sqlx::query!(
+            r#"
+            SELECT version, old_value, new_value, changed_by, changed_at FROM config_history
+            WHERE guild = $1 AND name = $2 AND version = $3;
+            "#,
+            guild.0 as i64,
+            key,
+            version
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(r.map(|r| ConfigChange {
+            version: r.version,
+            old_value: r.old_value,
+            new_value: r.new_value,
+            changed_by: r.changed_by.map(|u| UserId::from(u as u64)),
+            changed_at: r.changed_at,
+        }))
+    }
+
+    async fn add_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO joinable_roles (guild, role) VALUES ($1, $2);",
+            guild.0 as i64,
+            role.0 as i64
+        )
+        .execute(&self.pool)
+        .await;
+
+        match res {
+            Err(e) if e.is_check() => Err(TooManyRoles.into()),
+            Err(e) if e.is_unique() => Err(AlreadyJoinable.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM joinable_roles WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_joinable(&self, guild: GuildId, role: RoleId) -> crate::error::Result<bool> {
+        Ok(sqlx::query_scalar!(
+            "SELECT COUNT(*) AS matching FROM joinable_roles WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap_or_default()
+            > 0)
+    }
+
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<RoleId>> {
+        let s: Vec<i64> = sqlx::query_scalar!(
+            "SELECT role FROM joinable_roles WHERE guild = $1 ORDER BY role ASC;",
+            guild.0 as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(s.into_iter().map(|r| RoleId::from(r as u64)).collect())
+    }
+
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO timed_events (target_user, guild, action, expiry) VALUES ($1, $2, $3, $4);
+            "#,
+            action.target_user().0 as i64,
+            action.guild().0 as i64,
+            action.kind().to_json(),
+            action.expiry()
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM timed_events WHERE target_user = $1
+                                       AND guild = $2
+                                       AND action = $3
+                                       AND expiry = $4;
+            "#,
+            action.target_user().0 as i64,
+            action.guild().0 as i64,
+            action.kind().to_json(),
+            action.expiry()
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn actions_for_user(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<Action>> {
+        let q: sqlx::query::Map<_, _, _> = sqlx::query_as!(
+            TimedRow,
+            r#"
+            SELECT target_user, guild, expiry, action FROM timed_events WHERE guild = $1 AND target_user = $2 ORDER BY expiry ASC;
+            "#,
+            guild.0 as i64,
+            user.0 as i64
+        );
+
+        Ok(q.try_map(TimedRow::into_action).fetch_all(&self.pool).await?)
+    }
+
+    async fn actions_before(&self, epoch: chrono::DateTime<Utc>, limit: usize) -> crate::error::Result<Vec<Action>> {
+        let q: sqlx::query::Map<_, _, _> = sqlx::query_as!(
+            TimedRow,
+            r#"
+            SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= $1 ORDER BY expiry ASC LIMIT $2;
+            "#,
+            epoch,
+            limit as i64
+        );
+
+        Ok(q.try_map(TimedRow::into_action).fetch_all(&self.pool).await?)
+    }
+
+    async fn store_sticky_roles(&self, guild: GuildId, user: UserId, roles: &[RoleId]) -> crate::error::Result<()> {
+        let roles: Vec<i64> = roles.iter().map(|r| r.0 as i64).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO sticky_roles (guild, target_user, role)
+            SELECT $1, $2, UNNEST($3::BIGINT[])
+            ON CONFLICT DO NOTHING;
+            "#,
+            guild.0 as i64,
+            user.0 as i64,
+            &roles
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_sticky_roles(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<RoleId>> {
+        let s: Vec<i64> = sqlx::query_scalar!(
+            "DELETE FROM sticky_roles WHERE guild = $1 AND target_user = $2 RETURNING role;",
+            guild.0 as i64,
+            user.0 as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(s.into_iter().map(|r| RoleId::from(r as u64)).collect())
+    }
+
+    async fn drop_sticky_role(&self, guild: GuildId, user: UserId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM sticky_roles WHERE guild = $1 AND target_user = $2 AND role = $3;",
+            guild.0 as i64,
+            user.0 as i64,
+            role.0 as i64
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn risk_record(&self, guild: GuildId, user: UserId) -> crate::error::Result<Option<RiskRecord>> {
+        Ok(sqlx::query_as!(
+            RiskRecord,
+            "SELECT messages, warns, mutes, peak_pressure FROM member_risk WHERE guild = $1 AND target_user = $2;",
+            guild.0 as i64,
+            user.0 as i64
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn record_risk_message(&self, guild: GuildId, user: UserId, limit: i32) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO member_risk (guild, target_user, messages) VALUES ($1, $2, 1)
+            ON CONFLICT (guild, target_user) DO UPDATE SET messages = member_risk.messages + 1
+            WHERE member_risk.messages < $3;
+            "#,
+            guild.0 as i64,
+            user.0 as i64,
+            limit
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_risk_action(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        warns: i32,
+        mutes: i32,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO member_risk (guild, target_user, warns, mutes) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild, target_user) DO UPDATE
+            SET warns = member_risk.warns + $3, mutes = member_risk.mutes + $4;
+            "#,
+            guild.0 as i64,
+            user.0 as i64,
+            warns,
+            mutes
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_peak_pressures(&self, guild: GuildId, pressures: &[(UserId, f64)]) -> crate::error::Result<()> {
+        let users: Vec<i64> = pressures.iter().map(|(u, _)| u.0 as i64).collect();
+        let values: Vec<f64> = pressures.iter().map(|(_, p)| *p).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO member_risk (guild, target_user, peak_pressure)
+            SELECT $1, UNNEST($2::BIGINT[]), UNNEST($3::DOUBLE PRECISION[])
+            ON CONFLICT (guild, target_user) DO UPDATE
+            SET peak_pressure = GREATEST(member_risk.peak_pressure, EXCLUDED.peak_pressure);
+            "#,
+            guild.0 as i64,
+            &users,
+            &values
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn load_spam_pressure(&self, guild: GuildId) -> crate::error::Result<Vec<StoredPressure>> {
+        let rows = sqlx::query!(
+            "SELECT target_user, pressure, updated_at FROM spam_pressure WHERE guild = $1;",
+            guild.0 as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| (UserId::from(r.target_user as u64), r.pressure, r.updated_at))
+            .collect())
+    }
+
+    async fn replace_spam_pressure(&self, guild: GuildId, pressures: &[StoredPressure]) -> crate::error::Result<()> {
+        let users: Vec<i64> = pressures.iter().map(|(u, _, _)| u.0 as i64).collect();
+        let values: Vec<f64> = pressures.iter().map(|(_, p, _)| *p).collect();
+        let times: Vec<chrono::DateTime<Utc>> = pressures.iter().map(|(_, _, t)| *t).collect();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("DELETE FROM spam_pressure WHERE guild = $1;", guild.0 as i64)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO spam_pressure (guild, target_user, pressure, updated_at)
+            SELECT $1, UNNEST($2::BIGINT[]), UNNEST($3::DOUBLE PRECISION[]), UNNEST($4::TIMESTAMPTZ[]);
+            "#,
+            guild.0 as i64,
+            &users,
+            &values,
+            &times
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+//! Contains the SQLite storage backend, for small single-process deployments which don't want to run a database server.
+//! Config changes aren't announced to other processes, so several processes shouldn't share one SQLite database.
+
+use std::str::FromStr;
+
+use chrono::{TimeZone, Utc};
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::migrate::Migrator;
+use sqlx::pool::PoolOptions;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::db::storage::{
+    AlreadyJoinable, ConfigChange, RiskRecord, Storage, StoredPressure, TooManyRoles, MAX_JOINABLE_ROLES,
+};
+use crate::db::timed::Action;
+
+/// The SQL migrations for SQLite databases, applied automatically on startup.
+static MIGRATIONS: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// Stores glimbot's state in an SQLite database.
+pub struct SqliteStorage {
+    /// The connection pool. It holds a single connection, because SQLite only allows one writer at a time
+    /// and in-memory databases only live as long as their connection.
+    pool: SqlitePool,
+}
+
+/// Converts a timestamp to the microseconds since the Unix epoch it's stored as.
+fn to_micros(t: chrono::DateTime<Utc>) -> i64 {
+    t.timestamp() * 1_000_000 + t.timestamp_subsec_micros() as i64
+}
+
+/// Converts stored microseconds since the Unix epoch back into a timestamp.
+fn from_micros(t: i64) -> chrono::DateTime<Utc> {
+    Utc.timestamp(t.div_euclid(1_000_000), (t.rem_euclid(1_000_000) * 1000) as u32)
+}
+
+/// Parses a stored JSON value.
+fn from_json(s: &str) -> crate::error::Result<serde_json::Value> {
+    Ok(serde_json::from_str(s)?)
+}
+
+/// A row from `timed_events`.
+type TimedRow = (i64, i64, i64, String);
+
+/// Converts a row from `timed_events` into an action.
+fn timed_row_to_action((target_user, guild, expiry, action): TimedRow) -> crate::error::Result<Action> {
+    Ok(Action::new(
+        UserId::from(target_user as u64),
+        GuildId::from(guild as u64),
+        serde_json::from_str(&action)?,
+        from_micros(expiry),
+    ))
+}
+
+/// A row from `config_history`.
+type HistoryRow = (i32, Option<String>, Option<String>, Option<i64>, i64);
+
+/// Converts a row from `config_history` into a config change.
+fn history_row_to_change(
+    (version, old_value, new_value, changed_by, changed_at): HistoryRow,
+) -> crate::error::Result<ConfigChange> {
+    Ok(ConfigChange {
+        version,
+        old_value: old_value.as_deref().map(from_json).transpose()?,
+        new_value: new_value.as_deref().map(from_json).transpose()?,
+        changed_by: changed_by.map(|u| UserId::from(u as u64)),
+        changed_at: from_micros(changed_at),
+    })
+}
+
+impl SqliteStorage {
+    /// Opens the database, creating it if necessary, and runs migrations if necessary.
+    pub async fn connect(db_url: &str) -> crate::error::Result<Self> {
+        let opts = SqliteConnectOptions::from_str(db_url)?.create_if_missing(true);
+        let pool = PoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(opts)
+            .await?;
+
+        info!("Running DB migrations if necessary.");
+        MIGRATIONS.run(&pool).await?;
+        Ok(SqliteStorage { pool })
+    }
+
+    /// Sets a config value and records the change in the config history as part of a larger transaction.
+    /// The history is written first: the insert takes SQLite's write lock before reading the latest version, so
+    /// concurrent changes to the same key can't both claim the next one.
+    async fn write_config(
+        tx: &mut Transaction<'_, Sqlite>,
+        guild: GuildId,
+        key: &str,
+        v: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO config_history (guild, name, version, old_value, new_value, changed_by, changed_at)
+            SELECT ?1, ?2, COALESCE(MAX(version), 0) + 1,
+                   (SELECT value FROM config_values WHERE guild = ?1 AND name = ?2), ?3, ?4, ?5
+            FROM config_history WHERE guild = ?1 AND name = ?2;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(key)
+        .bind(v.to_string())
+        .bind(actor.map(|u| u.0 as i64))
+        .bind(to_micros(Utc::now()))
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO config_values (guild, name, value) VALUES (?1, ?2, ?3)
+            ON CONFLICT (guild, name) DO UPDATE SET value = excluded.value;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(key)
+        .bind(v.to_string())
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the guild's joinable roles as part of a larger transaction.
+    async fn replace_joinable_roles(
+        tx: &mut Transaction<'_, Sqlite>,
+        guild: GuildId,
+        roles: &[RoleId],
+    ) -> crate::error::Result<()> {
+        if roles.len() as i64 > MAX_JOINABLE_ROLES {
+            return Err(TooManyRoles.into());
+        }
+
+        sqlx::query("DELETE FROM joinable_roles WHERE guild = ?1;")
+            .bind(guild.0 as i64)
+            .execute(&mut *tx)
+            .await?;
+        for role in roles {
+            sqlx::query("INSERT INTO joinable_roles (guild, role) VALUES (?1, ?2) ON CONFLICT DO NOTHING;")
+                .bind(guild.0 as i64)
+                .bind(role.0 as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for SqliteStorage {
+    async fn get_config(&self, guild: GuildId, key: &str) -> crate::error::Result<Option<serde_json::Value>> {
+        let v: Option<String> = sqlx::query_scalar("SELECT value FROM config_values WHERE guild = ?1 AND name = ?2;")
+            .bind(guild.0 as i64)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        v.as_deref().map(from_json).transpose()
+    }
+
+    async fn get_or_insert_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        default: serde_json::Value,
+    ) -> crate::error::Result<serde_json::Value> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO config_values (guild, name, value) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING;")
+            .bind(guild.0 as i64)
+            .bind(key)
+            .bind(default.to_string())
+            .execute(&mut tx)
+            .await?;
+        let v: String = sqlx::query_scalar("SELECT value FROM config_values WHERE guild = ?1 AND name = ?2;")
+            .bind(guild.0 as i64)
+            .bind(key)
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+        from_json(&v)
+    }
+
+    async fn set_config(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::write_config(&mut tx, guild, key, value, actor).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_config(&self, guild: GuildId, key: &str, actor: Option<UserId>) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO config_history (guild, name, version, old_value, new_value, changed_by, changed_at)
+            SELECT guild, name,
+                   (SELECT COALESCE(MAX(version), 0) + 1 FROM config_history WHERE guild = ?1 AND name = ?2),
+                   value, NULL, ?3, ?4
+            FROM config_values WHERE guild = ?1 AND name = ?2;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(key)
+        .bind(actor.map(|u| u.0 as i64))
+        .bind(to_micros(Utc::now()))
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM config_values WHERE guild = ?1 AND name = ?2;")
+            .bind(guild.0 as i64)
+            .bind(key)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn import_config(
+        &self,
+        guild: GuildId,
+        values: &[(String, serde_json::Value)],
+        joinable_roles: Option<&[RoleId]>,
+        actor: Option<UserId>,
+    ) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (key, value) in values {
+            Self::write_config(&mut tx, guild, key, value, actor).await?;
+        }
+        if let Some(roles) = joinable_roles {
+            Self::replace_joinable_roles(&mut tx, guild, roles).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn config_history(&self, guild: GuildId, key: &str, limit: i64) -> crate::error::Result<Vec<ConfigChange>> {
+        let rows: Vec<HistoryRow> = sqlx::query_as(
+            r#"
+            SELECT version, old_value, new_value, changed_by, changed_at FROM config_history
+            WHERE guild = ?1 AND name = ?2
+            ORDER BY version DESC LIMIT ?3;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(key)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(history_row_to_change).collect()
+    }
+
+    async fn config_version(
+        &self,
+        guild: GuildId,
+        key: &str,
+        version: i32,
+    ) -> crate::error::Result<Option<ConfigChange>> {
+        let row: Option<HistoryRow> = sqlx::query_as(
+            r#"
+            SELECT version, old_value, new_value, changed_by, changed_at FROM config_history
+            WHERE guild = ?1 AND name = ?2 AND version = ?3;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(key)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(history_row_to_change).transpose()
+    }
+
+    async fn add_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let inserted = sqlx::query("INSERT INTO joinable_roles (guild, role) VALUES (?1, ?2) ON CONFLICT DO NOTHING;")
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        if inserted == 0 {
+            return Err(AlreadyJoinable.into());
+        }
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM joinable_roles WHERE guild = ?1;")
+            .bind(guild.0 as i64)
+            .fetch_one(&mut tx)
+            .await?;
+        if count > MAX_JOINABLE_ROLES {
+            return Err(TooManyRoles.into());
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query("DELETE FROM joinable_roles WHERE guild = ?1 AND role = ?2;")
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_joinable(&self, guild: GuildId, role: RoleId) -> crate::error::Result<bool> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM joinable_roles WHERE guild = ?1 AND role = ?2;")
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count > 0)
+    }
+
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<RoleId>> {
+        let s: Vec<i64> = sqlx::query_scalar("SELECT role FROM joinable_roles WHERE guild = ?1 ORDER BY role ASC;")
+            .bind(guild.0 as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(s.into_iter().map(|r| RoleId::from(r as u64)).collect())
+    }
+
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
+        sqlx::query("INSERT INTO timed_events (target_user, guild, action, expiry) VALUES (?1, ?2, ?3, ?4);")
+            .bind(action.target_user().0 as i64)
+            .bind(action.guild().0 as i64)
+            .bind(action.kind().to_json().to_string())
+            .bind(to_micros(action.expiry()))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM timed_events WHERE target_user = ?1
+                                       AND guild = ?2
+                                       AND action = ?3
+                                       AND expiry = ?4;
+            "#,
+        )
+        .bind(action.target_user().0 as i64)
+        .bind(action.guild().0 as i64)
+        .bind(action.kind().to_json().to_string())
+        .bind(to_micros(action.expiry()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn actions_for_user(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<Action>> {
+        let rows: Vec<TimedRow> = sqlx::query_as(
+            r#"
+            SELECT target_user, guild, expiry, action FROM timed_events WHERE guild = ?1 AND target_user = ?2 ORDER BY expiry ASC;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(user.0 as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(timed_row_to_action).collect()
+    }
+
+    async fn actions_before(&self, epoch: chrono::DateTime<Utc>, limit: usize) -> crate::error::Result<Vec<Action>> {
+        let rows: Vec<TimedRow> = sqlx::query_as(
+            r#"
+            SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= ?1 ORDER BY expiry ASC LIMIT ?2;
+            "#,
+        )
+        .bind(to_micros(epoch))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(timed_row_to_action).collect()
+    }
+
+    async fn store_sticky_roles(&self, guild: GuildId, user: UserId, roles: &[RoleId]) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for role in roles {
+            sqlx::query(
+                "INSERT INTO sticky_roles (guild, target_user, role) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING;",
+            )
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn take_sticky_roles(&self, guild: GuildId, user: UserId) -> crate::error::Result<Vec<RoleId>> {
+        let mut tx = self.pool.begin().await?;
+        let s: Vec<i64> = sqlx::query_scalar("SELECT role FROM sticky_roles WHERE guild = ?1 AND target_user = ?2;")
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .fetch_all(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM sticky_roles WHERE guild = ?1 AND target_user = ?2;")
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(s.into_iter().map(|r| RoleId::from(r as u64)).collect())
+    }
+
+    async fn drop_sticky_role(&self, guild: GuildId, user: UserId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query("DELETE FROM sticky_roles WHERE guild = ?1 AND target_user = ?2 AND role = ?3;")
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn risk_record(&self, guild: GuildId, user: UserId) -> crate::error::Result<Option<RiskRecord>> {
+        let row: Option<(i32, i32, i32, f64)> = sqlx::query_as(
+            "SELECT messages, warns, mutes, peak_pressure FROM member_risk WHERE guild = ?1 AND target_user = ?2;",
+        )
+        .bind(guild.0 as i64)
+        .bind(user.0 as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(messages, warns, mutes, peak_pressure)| RiskRecord {
+            messages,
+            warns,
+            mutes,
+            peak_pressure,
+        }))
+    }
+
+    async fn record_risk_message(&self, guild: GuildId, user: UserId, limit: i32) -> crate::error::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO member_risk (guild, target_user, messages) VALUES (?1, ?2, 1)
+            ON CONFLICT (guild, target_user) DO UPDATE SET messages = member_risk.messages + 1
+            WHERE member_risk.messages < ?3;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(user.0 as i64)
+        .bind(limit)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_risk_action(
+        &self,
+        guild: GuildId,
+        user: UserId,
+        warns: i32,
+        mutes: i32,
+    ) -> crate::error::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO member_risk (guild, target_user, warns, mutes) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (guild, target_user) DO UPDATE
+            SET warns = member_risk.warns + ?3, mutes = member_risk.mutes + ?4;
+            "#,
+        )
+        .bind(guild.0 as i64)
+        .bind(user.0 as i64)
+        .bind(warns)
+        .bind(mutes)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_peak_pressures(&self, guild: GuildId, pressures: &[(UserId, f64)]) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (user, pressure) in pressures {
+            sqlx::query(
+                r#"
+                INSERT INTO member_risk (guild, target_user, peak_pressure) VALUES (?1, ?2, ?3)
+                ON CONFLICT (guild, target_user) DO UPDATE
+                SET peak_pressure = MAX(member_risk.peak_pressure, excluded.peak_pressure);
+                "#,
+            )
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .bind(*pressure)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_spam_pressure(&self, guild: GuildId) -> crate::error::Result<Vec<StoredPressure>> {
+        let rows: Vec<(i64, f64, i64)> =
+            sqlx::query_as("SELECT target_user, pressure, updated_at FROM spam_pressure WHERE guild = ?1;")
+                .bind(guild.0 as i64)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(u, p, t)| (UserId::from(u as u64), p, from_micros(t)))
+            .collect())
+    }
+
+    async fn replace_spam_pressure(&self, guild: GuildId, pressures: &[StoredPressure]) -> crate::error::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM spam_pressure WHERE guild = ?1;")
+            .bind(guild.0 as i64)
+            .execute(&mut tx)
+            .await?;
+        for (user, pressure, updated_at) in pressures {
+            sqlx::query(
+                "INSERT INTO spam_pressure (guild, target_user, pressure, updated_at) VALUES (?1, ?2, ?3, ?4);",
+            )
+            .bind(guild.0 as i64)
+            .bind(user.0 as i64)
+            .bind(*pressure)
+            .bind(to_micros(*updated_at))
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
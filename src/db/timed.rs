@@ -7,14 +7,17 @@ use std::fmt::Formatter;
 use chrono::Duration;
 use chrono::Utc;
 use once_cell::sync::Lazy;
-use serenity::model::id::{GuildId, UserId};
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::misc::Mentionable;
 use serenity::prelude::Context;
-use sqlx::PgPool;
 
+use crate::db::storage::Storage;
 use crate::db::DbContext;
-use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::config::{RoleExt, VerifiedRole};
 use crate::dispatch::Dispatch;
-use crate::module::moderation::NoMuteRoleSet;
+use crate::error::LogErrorExt;
+use crate::module::moderation::{send_to_mod_log, NoMuteRoleSet};
+use crate::module::sticky::StickyRoles;
 
 /// The kind of action to be taken once a timed event is processed.
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
@@ -24,6 +27,8 @@ pub enum ActionKind {
     Ban,
     /// A user needs to be unmuted.
     Mute,
+    /// A temporarily assigned role needs to be removed from a user.
+    RemoveRole(RoleId),
     /// Prints a debug message to the logger.
     Debug,
 }
@@ -93,12 +98,13 @@ impl ActionFailure {
         match self.action.kind {
             ActionKind::Ban => "could not unban",
             ActionKind::Mute => "could not unmute",
+            ActionKind::RemoveRole(_) => "could not remove temporary role",
             ActionKind::Debug => "could not print debug statement",
         }
     }
 
     /// Returns a string describing the failure that occurred.
-    pub fn failure_info(&self) -> Cow<str> {
+    pub fn failure_info(&self) -> Cow<'_, str> {
         match &self.kind {
             FailureKind::UserNotInGuild => {
                 format!("user {} is not a member of this guild", self.action.target_user).into()
@@ -126,14 +132,20 @@ impl Action {
         let res: Result<(), ActionFailure> = match self.kind {
             ActionKind::Ban => self.do_unban(ctx).await,
             ActionKind::Mute => self.do_unmute(dis, db.clone(), ctx).await,
+            ActionKind::RemoveRole(role) => self.do_remove_role(dis, ctx, role).await,
             ActionKind::Debug => {
                 debug!("Got debug action: {:?}", self);
                 Ok(())
             }
         };
 
-        if let Err(e) = res {
-            warn!("{}", e);
+        match res {
+            Err(e) => warn!("{}", e),
+            Ok(_) => {
+                if let ActionKind::RemoveRole(role) = self.kind {
+                    self.report_role_expiry(dis, ctx, role).await.log_error();
+                }
+            }
         }
 
         let t = TimedEvents::new(db);
@@ -157,24 +169,48 @@ impl Action {
             .map_err(|e| ActionFailure::from_err(*self, e))?
             .ok_or_else(|| ActionFailure::from_err(*self, NoMuteRoleSet))?;
 
-        let mut mem = self
-            .guild
-            .member(ctx, self.target_user)
-            .await
-            .map_err(|_| ActionFailure::new(*self, FailureKind::UserNotInGuild))?;
+        self.do_remove_role(dis, ctx, mute_role.into_inner()).await
+    }
 
-        if mem.roles.contains(&mute_role.into_inner()) {
-            debug!("unmuting user");
-            mem.remove_role(ctx, mute_role.into_inner())
+    /// Removes a role from a user in a guild, if they still have it. If the user has left the guild,
+    /// the role is removed from their sticky roles so it isn't re-applied when they rejoin.
+    #[instrument(level = "debug", skip(self, dis, ctx))]
+    async fn do_remove_role(&self, dis: &Dispatch, ctx: &Context, role: RoleId) -> Result<(), ActionFailure> {
+        let mut mem = match self.guild.member(ctx, self.target_user).await {
+            Ok(m) => m,
+            Err(_) => {
+                debug!("user not in guild; removing role from sticky roles");
+                StickyRoles::new(dis.db(self.guild))
+                    .drop_role(self.target_user, role)
+                    .await
+                    .map_err(|e| ActionFailure::from_err(*self, e))?;
+                return Err(ActionFailure::new(*self, FailureKind::UserNotInGuild));
+            }
+        };
+
+        if mem.roles.contains(&role) {
+            debug!("removing role from user");
+            mem.remove_role(ctx, role)
                 .await
                 .map_err(|e| ActionFailure::from_err(*self, e))?;
         } else {
-            debug!("user wasn't muted");
+            debug!("user didn't have the role");
         }
 
         Ok(())
     }
 
+    /// Notes in the mod log that a temporary role was removed.
+    async fn report_role_expiry(&self, dis: &Dispatch, ctx: &Context, role: RoleId) -> crate::error::Result<()> {
+        let role_name = role.to_role_name_or_id(ctx, self.guild).await;
+        send_to_mod_log(dis, ctx, self.guild, |emb| {
+            emb.title("Temporary role expired")
+                .field("User", self.target_user.mention(), false)
+                .field("Role", role_name, false)
+        })
+        .await
+    }
+
     /// Unbans a user in a guild.
     #[instrument(level = "debug", skip(self, ctx))]
     async fn do_unban(&self, ctx: &Context) -> Result<(), ActionFailure> {
@@ -191,6 +227,21 @@ impl Action {
     pub fn guild(&self) -> GuildId {
         self.guild
     }
+
+    /// Accessor for the user affected by the action.
+    pub fn target_user(&self) -> UserId {
+        self.target_user
+    }
+
+    /// Accessor for the kind of action.
+    pub fn kind(&self) -> ActionKind {
+        self.kind
+    }
+
+    /// Accessor for when the action will be taken.
+    pub fn expiry(&self) -> chrono::DateTime<Utc> {
+        self.expiry
+    }
 }
 
 /// A duration representing one minute.
@@ -198,14 +249,6 @@ pub static ONE_MINUTE: Lazy<Duration> = Lazy::new(|| Duration::minutes(1));
 /// A duration representing about one hundred years.
 pub static ONE_HUNDREDISH_YEARS: Lazy<Duration> = Lazy::new(|| Duration::days(365 * 100));
 
-#[doc(hidden)]
-struct Row {
-    target_user: i64,
-    guild: i64,
-    expiry: chrono::DateTime<Utc>,
-    action: serde_json::Value,
-}
-
 /// A wrapper for a database context for performing actions with timed actions.
 #[derive(Clone)]
 pub struct TimedEvents<'pool> {
@@ -224,61 +267,28 @@ impl<'pool> TimedEvents<'pool> {
 
     /// Stores an action in the database.
     pub async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
-        sqlx::query!(
-            r#"
-            INSERT INTO timed_events (target_user, guild, action, expiry) VALUES ($1, $2, $3, $4);
-            "#,
-            action.target_user.0 as i64,
-            self.context.guild_as_i64(),
-            action.kind.to_json(),
-            action.expiry.clone()
-        )
-        .execute(self.context.conn())
-        .await?;
-        Ok(())
+        self.context.storage().store_action(action).await
     }
 
     /// Deletes an action from the database.
     pub async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
-        sqlx::query!(
-            r#"
-            DELETE FROM timed_events WHERE target_user = $1
-                                       AND guild = $2
-                                       AND action = $3
-                                       AND expiry = $4;
-            "#,
-            action.target_user.0 as i64,
-            self.context.guild_as_i64(),
-            action.kind.to_json(),
-            action.expiry.clone()
-        )
-        .execute(self.context.conn())
-        .await?;
-        Ok(())
+        self.context.storage().drop_action(action).await
+    }
+
+    /// Retrieves the pending actions against a user in the guild, soonest first.
+    pub async fn actions_for_user(&self, user: UserId) -> crate::error::Result<Vec<Action>> {
+        self.context
+            .storage()
+            .actions_for_user(self.context.guild(), user)
+            .await
     }
 
     /// Retrieves the actions before the specified epoch, limited by `BATCH_LIMIT`.
-    pub async fn get_actions_before(pool: &PgPool, epoch: chrono::DateTime<Utc>) -> crate::error::Result<Vec<Action>> {
-        let q: sqlx::query::Map<_, _, _> = sqlx::query_as!(
-            Row,
-            r#"
-            SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= $1 ORDER BY expiry ASC LIMIT $2;
-            "#,
-            epoch,
-            Self::BATCH_LIMIT as i64
-        );
-
-        q.try_map(|r: Row| {
-            Ok(Action::new(
-                (r.target_user as u64).into(),
-                (r.guild as u64).into(),
-                serde_json::from_value(r.action).map_err(|e| sqlx::Error::Decode(e.into()))?,
-                r.expiry,
-            ))
-        })
-        .fetch_all(pool)
-        .await
-        .map_err(crate::error::Error::from)
+    pub async fn get_actions_before(
+        storage: &dyn Storage,
+        epoch: chrono::DateTime<Utc>,
+    ) -> crate::error::Result<Vec<Action>> {
+        storage.actions_before(epoch, Self::BATCH_LIMIT).await
     }
 }
 
@@ -316,6 +326,11 @@ impl Action {
         Self::with_duration(user, guild, ActionKind::Mute, duration)
     }
 
+    /// Creates an action to remove a temporarily assigned role from a user.
+    pub fn remove_role(user: UserId, guild: GuildId, role: RoleId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(user, guild, ActionKind::RemoveRole(role), duration)
+    }
+
     /// Creates an action to print a debug message.
     pub fn debug(duration: impl Into<chrono::Duration>) -> Self {
         Self::with_duration(Default::default(), Default::default(), ActionKind::Debug, duration)
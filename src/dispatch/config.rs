@@ -7,6 +7,7 @@ use std::str::FromStr;
 
 use downcast_rs::impl_downcast;
 use downcast_rs::DowncastSync;
+use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serenity::client::Context;
@@ -16,6 +17,7 @@ use serenity::model::misc::Mentionable;
 
 use crate::db::DbContext;
 use crate::error::{GuildNotInCache, IntoBotErr};
+use crate::util::constraints::FieldConstraint;
 use std::sync::Arc;
 
 /// A trait specifying that a type can be set as a value.
@@ -35,6 +37,12 @@ where
     help: &'static str,
     /// A default value which can be used if `T: Clone` to set an unset config value.
     default: Option<Box<dyn Fn() -> T + Send + Sync>>,
+    /// Whether users may unset the value entirely.
+    clearable: bool,
+    /// Constraints on the value's fields, checked whenever it's set.
+    constraints: Vec<FieldConstraint>,
+    /// An example of what users can set the value to.
+    example: Option<&'static str>,
 }
 
 impl<T> fmt::Debug for Value<T>
@@ -49,6 +57,9 @@ where
                 "default",
                 &self.default.as_ref().map(|_| "present").unwrap_or("not present") as &dyn fmt::Debug,
             )
+            .field("clearable", &self.clearable as &dyn fmt::Debug)
+            .field("constraints", &self.constraints as &dyn fmt::Debug)
+            .field("example", &self.example as &dyn fmt::Debug)
             .finish()
     }
 }
@@ -70,9 +81,37 @@ where
             name,
             help,
             default: None,
+            clearable: false,
+            constraints: Vec::new(),
+            example: None,
         }
     }
 
+    /// Specifies whether users may unset the value with `config unset`. Values which are required for a module to
+    /// work shouldn't be clearable.
+    pub fn with_clearable(mut self, clearable: bool) -> Self {
+        self.clearable = clearable;
+        self
+    }
+
+    /// Adds a constraint on one of the value's fields, which is checked whenever the value is set.
+    pub fn with_constraint(mut self, constraint: FieldConstraint) -> Self {
+        self.constraints.push(constraint);
+        self
+    }
+
+    /// Adds several constraints at once. See [`Value::with_constraint`].
+    pub fn with_constraints(mut self, constraints: impl IntoIterator<Item = FieldConstraint>) -> Self {
+        self.constraints.extend(constraints);
+        self
+    }
+
+    /// Specifies an example of what users can set the value to, as they'd type it in `config set`.
+    pub fn with_example(mut self, example: &'static str) -> Self {
+        self.example = Some(example);
+        self
+    }
+
     /// Creates a value with the given name and help, and with the specified default.
     pub fn with_default<F>(name: &'static str, help: &'static str, default: F) -> Self
     where
@@ -111,6 +150,140 @@ where
     pub async fn set(&self, ctx: &DbContext<'_>, value: T) -> crate::error::Result<()> {
         ctx.insert(self.name, value).await
     }
+
+    /// Removes the value associated with this value's name, so it's unset or returns to its default.
+    pub async fn remove(&self, ctx: &DbContext<'_>) -> crate::error::Result<()> {
+        ctx.remove(self.name).await
+    }
+}
+
+impl_err!(NoSuchField, "That config value has no such field.", true);
+impl_err!(
+    NotStructured,
+    "Only JSON object and list config values have fields.",
+    true
+);
+
+/// A config key with an optional dotted path to a field inside a JSON-shaped value, i.e. `spam_config.max_pressure`
+/// or `spam_config.responses.0.action`. List elements are addressed by index.
+#[derive(Debug, Clone)]
+pub struct ConfigPath<'a> {
+    /// The config key.
+    pub key: &'a str,
+    /// The fields to descend through, outermost first.
+    pub fields: Vec<&'a str>,
+}
+
+impl<'a> ConfigPath<'a> {
+    /// Splits a dotted path into the config key and its fields.
+    pub fn parse(s: &'a str) -> Self {
+        let mut parts = s.split('.');
+        let key = parts.next().unwrap_or_default();
+        ConfigPath {
+            key,
+            fields: parts.collect(),
+        }
+    }
+
+    /// Whether this path refers to a field rather than a whole value.
+    pub fn is_field(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    /// Descends one level into a JSON value.
+    fn child<'v>(v: &'v serde_json::Value, field: &str) -> crate::error::Result<&'v serde_json::Value> {
+        match v {
+            serde_json::Value::Object(m) => m.get(field).ok_or_else(|| NoSuchField.into()),
+            serde_json::Value::Array(a) => field
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| a.get(i))
+                .ok_or_else(|| NoSuchField.into()),
+            _ => Err(NotStructured.into()),
+        }
+    }
+
+    /// Retrieves the field this path refers to.
+    pub fn get<'v>(&self, v: &'v serde_json::Value) -> crate::error::Result<&'v serde_json::Value> {
+        self.fields.iter().try_fold(v, |v, f| Self::child(v, f))
+    }
+
+    /// Replaces the field this path refers to. The last field may be new if its parent is an object, so optional
+    /// fields which aren't set yet can be filled in.
+    pub fn set(&self, v: &mut serde_json::Value, new: serde_json::Value) -> crate::error::Result<()> {
+        let (last, parents) = match self.fields.split_last() {
+            Some(x) => x,
+            None => {
+                *v = new;
+                return Ok(());
+            }
+        };
+
+        let mut cur = v;
+        for f in parents {
+            cur = match cur {
+                serde_json::Value::Object(m) => m.get_mut(*f).ok_or(NoSuchField)?,
+                serde_json::Value::Array(a) => f
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(move |i| a.get_mut(i))
+                    .ok_or(NoSuchField)?,
+                _ => return Err(NotStructured.into()),
+            };
+        }
+
+        match cur {
+            serde_json::Value::Object(m) => {
+                m.insert(last.to_string(), new);
+            }
+            serde_json::Value::Array(a) => {
+                let slot = last
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| a.get_mut(i))
+                    .ok_or(NoSuchField)?;
+                *slot = new;
+            }
+            _ => return Err(NotStructured.into()),
+        }
+        Ok(())
+    }
+
+    /// Interprets a user-supplied field value as JSON, falling back to a plain string so that values like
+    /// durations don't need quoting.
+    pub fn parse_field_value(s: &str) -> serde_json::Value {
+        serde_json::from_str(s).unwrap_or_else(|_| serde_json::Value::String(s.to_string()))
+    }
+}
+
+/// Describes the shape of a config value, for display in `config info` and `config list`.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    /// The name of the value's type, without module paths.
+    pub type_name: String,
+    /// Constraints on the value's fields.
+    pub constraints: Vec<FieldConstraint>,
+    /// An example of what users can set the value to.
+    pub example: Option<&'static str>,
+    /// The default value, as it would be displayed by `config get`.
+    pub default: Option<String>,
+}
+
+/// Strips module paths from a type name, i.e. `std::option::Option<glimbot::Foo>` becomes `Option<Foo>`.
+fn short_type_name(full: &str) -> String {
+    let mut out = String::with_capacity(full.len());
+    let mut segment = String::new();
+    for c in full.chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            segment.push(c);
+        } else {
+            out.push_str(segment.rsplit("::").next().unwrap_or_default());
+            segment.clear();
+            out.push(c);
+        }
+    }
+    out.push_str(segment.rsplit("::").next().unwrap_or_default());
+    out
 }
 
 /// Trait for converting arbitrary types from strings into values
@@ -165,8 +338,20 @@ pub trait Validator: Send + Sync + Any + DowncastSync + 'static {
     async fn get_json(&self, db: &DbContext<'_>) -> crate::error::Result<Option<serde_json::Value>>;
     /// Inserts value into DB.
     async fn insert_json(&self, v: serde_json::Value, db: &DbContext<'_>) -> crate::error::Result<()>;
+    /// Replaces the cached value without touching the DB, i.e. after it was written as part of a larger transaction.
+    async fn cache_json(&self, v: serde_json::Value, db: &DbContext<'_>) -> crate::error::Result<()>;
     /// Converts a JSON representation of the associated type into a string.
     fn display_value(&self, v: serde_json::Value) -> crate::error::Result<String>;
+    /// Removes the value from the DB, so it's unset or returns to its default.
+    async fn remove(&self, db: &DbContext<'_>) -> crate::error::Result<()>;
+    /// Whether users may unset the value.
+    fn clearable(&self) -> bool;
+    /// Whether the value has a default it returns to when removed.
+    fn has_default(&self) -> bool;
+    /// The JSON representation of the default value, if there is one.
+    fn default_json(&self) -> crate::error::Result<Option<serde_json::Value>>;
+    /// Describes the value's type, constraints, example and default.
+    fn schema(&self) -> crate::error::Result<Schema>;
 }
 impl_downcast!(sync Validator);
 
@@ -185,7 +370,11 @@ where
 
     async fn validate(&self, ctx: &Context, gid: GuildId, s: &str) -> crate::error::Result<serde_json::Value> {
         let s: T = T::from_str_with_ctx(s, ctx, gid).await.into_user_err()?;
-        Ok(serde_json::to_value(s)?)
+        let v = serde_json::to_value(s)?;
+        for c in &self.constraints {
+            c.check(&v)?;
+        }
+        Ok(v)
     }
 
     async fn get_json(&self, db: &DbContext<'_>) -> crate::error::Result<Option<serde_json::Value>> {
@@ -198,10 +387,40 @@ where
         db.insert(self.name, v).await
     }
 
+    async fn cache_json(&self, v: serde_json::Value, db: &DbContext<'_>) -> crate::error::Result<()> {
+        let v = serde_json::from_value::<T>(v)?;
+        db.cache_insert(self.name, v).await
+    }
+
     fn display_value(&self, v: serde_json::Value) -> crate::error::Result<String> {
         let v: T = serde_json::from_value(v)?;
         Ok(v.to_string())
     }
+
+    async fn remove(&self, db: &DbContext<'_>) -> crate::error::Result<()> {
+        Value::remove(self, db).await
+    }
+
+    fn clearable(&self) -> bool {
+        self.clearable
+    }
+
+    fn has_default(&self) -> bool {
+        self.default.is_some()
+    }
+
+    fn default_json(&self) -> crate::error::Result<Option<serde_json::Value>> {
+        Ok(self.default.as_ref().map(|d| serde_json::to_value(d())).transpose()?)
+    }
+
+    fn schema(&self) -> crate::error::Result<Schema> {
+        Ok(Schema {
+            type_name: short_type_name(std::any::type_name::<T>()),
+            constraints: self.constraints.clone(),
+            example: self.example,
+            default: self.default.as_ref().map(|d| d().to_string()),
+        })
+    }
 }
 
 /// A role which has been verified to exist in a guild.
@@ -209,6 +428,11 @@ where
 pub struct VerifiedRole(RoleId);
 
 impl VerifiedRole {
+    /// Wraps a role which is already known to exist in a guild.
+    pub fn from_known(r: RoleId) -> VerifiedRole {
+        Self(r)
+    }
+
     /// Extracts the inner `RoleId`.
     pub fn into_inner(self) -> RoleId {
         self.0
@@ -249,6 +473,51 @@ impl RoleExt for RoleId {
 
 impl_err!(NoSuchRole, "There is no such role in this guild.", true);
 
+/// A list of roles which have each been verified to exist in a guild.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Eq, PartialEq)]
+pub struct VerifiedRoles(Vec<VerifiedRole>);
+
+impl VerifiedRoles {
+    /// Iterates over the contained roles.
+    pub fn iter(&self) -> impl Iterator<Item = RoleId> + '_ {
+        self.0.iter().map(|r| r.into_inner())
+    }
+
+    /// Returns true if the role is contained in this list.
+    pub fn contains(&self, role: RoleId) -> bool {
+        self.iter().any(|r| r == role)
+    }
+}
+
+#[async_trait::async_trait]
+impl FromStrWithCtx for VerifiedRoles {
+    type Err = crate::error::Error;
+
+    /// Parses a comma separated list of roles. `<none>`, as displayed for an empty list, parses as an empty list.
+    async fn from_str_with_ctx(s: &str, ctx: &Context, gid: GuildId) -> Result<Self, Self::Err> {
+        let mut out = Vec::new();
+        if s.trim() == "<none>" {
+            return Ok(Self(out));
+        }
+        for r in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let role = VerifiedRole::from_str_with_ctx(r, ctx, gid).await?;
+            if !out.contains(&role) {
+                out.push(role);
+            }
+        }
+        Ok(Self(out))
+    }
+}
+
+impl fmt::Display for VerifiedRoles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<none>");
+        }
+        write!(f, "{}", self.0.iter().join(", "))
+    }
+}
+
 #[async_trait::async_trait]
 impl FromStrWithCtx for VerifiedRole {
     type Err = crate::error::Error;
@@ -280,6 +549,16 @@ impl VerifiedChannel {
     pub fn from_known(c: ChannelId) -> VerifiedChannel {
         Self(c)
     }
+
+    /// Verifies that a channel given by ID is in the guild.
+    pub async fn from_id(c: ChannelId, ctx: &Context, gid: GuildId) -> crate::error::Result<VerifiedChannel> {
+        let guild_info = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+        if guild_info.channels.contains_key(&c) {
+            Ok(Self(c))
+        } else {
+            Err(NoSuchChannel.into())
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -12,26 +12,29 @@ use futures::stream;
 use futures::stream::StreamExt;
 use futures::TryStreamExt;
 
-use once_cell::sync::{OnceCell};
+use once_cell::sync::OnceCell;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use serenity::client::bridge::gateway::ShardManager;
 use serenity::client::{Context, EventHandler};
-use serenity::model::channel::Message;
+use serenity::model::channel::{GuildChannel, Message};
 use serenity::model::gateway::{Activity, Ready};
+use serenity::model::guild::Member;
 use serenity::model::id::{GuildId, UserId};
+use serenity::model::user::User;
 use serenity::prelude::TypeMapKey;
 use serenity::utils::MessageBuilder;
-use sqlx::PgPool;
 use tokio::sync::{watch, Mutex};
 use tracing::Instrument;
 
-use crate::db::cache::TimedCache;
+use crate::db::cache::{Cache, LruEvictionStrategy, TimedCache};
+use crate::db::storage::{MemoryStorage, Storage};
 use crate::db::timed::TimedEvents;
 use crate::db::{ConfigCache, DbContext};
 use crate::dispatch::config::ValueType;
 use crate::dispatch::message_info::MsgInfo;
 use crate::error::{LogErrorExt, SysError, UserError};
+use crate::module::risk::{RiskRecords, RISK_CACHE_TTL};
 use crate::module::Module;
 use crate::util::ordset::OrdSet;
 use std::num::NonZeroUsize;
@@ -40,6 +43,12 @@ pub mod config;
 pub mod message_info;
 
 pub const PER_GUILD_MESSAGE_CACHE_SIZE: usize = 4096;
+/// The most guilds to keep recent messages for. The least recently active guilds are dropped first.
+pub const MESSAGE_CACHE_GUILDS: usize = 1024;
+
+/// Recent messages in each guild. Each guild's set is bounded by [`PER_GUILD_MESSAGE_CACHE_SIZE`], and a guild is
+/// only dropped once it's among the least recently active, so busy guilds keep theirs indefinitely.
+pub type MessageCache = Cache<GuildId, OrdSet<MsgInfo>, LruEvictionStrategy>;
 
 /// The primary dispatch state holder. Contains information on the various modules
 /// and filters installed in Glimbot.
@@ -54,22 +63,44 @@ pub struct Dispatch {
     message_hooks: Vec<Arc<dyn Module>>,
     /// Modules containing tick-based hooks
     tick_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing member join/leave hooks.
+    member_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing channel creation hooks.
+    channel_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing shutdown hooks.
+    shutdown_hooks: Vec<Arc<dyn Module>>,
     /// Config value validators for the configuration values set in each guild.
     config_values: BTreeMap<&'static str, Arc<dyn config::Validator>>,
-    /// Database connection pool.
-    pool: PgPool,
+    /// The storage backend persistent state goes to.
+    storage: Arc<dyn Storage>,
     /// The background service, initialized on first start.
     background_service: OnceCell<Arc<BackgroundService>>,
     config_cache: ConfigCache,
-    message_cache: TimedCache<GuildId, OrdSet<MsgInfo>>,
+    message_cache: MessageCache,
+    /// Cached member risk records.
+    risk_records: RiskRecords,
     bot_id_channels: (watch::Sender<Option<UserId>>, watch::Receiver<Option<UserId>>),
     bot_id_local: thread_local::ThreadLocal<Mutex<watch::Receiver<Option<UserId>>>>,
 }
 
 impl Dispatch {
-    pub fn message_cache(&self) -> &TimedCache<GuildId, OrdSet<MsgInfo>> {
+    pub fn message_cache(&self) -> &MessageCache {
         &self.message_cache
     }
+
+    /// Retrieves the cached member risk records.
+    pub fn risk_records(&self) -> &RiskRecords {
+        &self.risk_records
+    }
+
+    /// Removes expired entries from the caches Dispatch owns.
+    pub fn sweep_caches(&self) {
+        let swept = self.message_cache.sweep() + self.config_cache.sweep() + self.risk_records.sweep();
+        if swept > 0 {
+            debug!("swept {} cache entries", swept);
+        }
+        trace!("message cache: {:?}", self.message_cache.metrics());
+    }
 }
 
 impl Dispatch {
@@ -96,9 +127,9 @@ impl Dispatch {
 }
 
 impl Dispatch {
-    /// Gets a reference to the DB pool.
-    pub fn pool(&self) -> &PgPool {
-        &self.pool
+    /// Gets a reference to the storage backend.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
     }
 }
 
@@ -131,7 +162,7 @@ impl Dispatch {
         self.owner
     }
     /// Convenience function for constructing a DbContext with the pool in this Dispatch.
-    pub fn db(&self, gid: GuildId) -> DbContext {
+    pub fn db(&self, gid: GuildId) -> DbContext<'_> {
         DbContext::new(self, gid)
     }
 }
@@ -166,24 +197,33 @@ impl_err!(
 );
 
 impl Dispatch {
-    /// Creates an empty dispatch with the given pool and owner.
-    pub fn new(owner: UserId, pool: PgPool) -> Self {
+    /// Creates an empty dispatch with the given storage backend and owner.
+    pub fn new(owner: UserId, storage: Arc<dyn Storage>) -> Self {
         Self {
             owner,
             filters: Vec::new(),
             modules: Default::default(),
             message_hooks: vec![],
             tick_hooks: vec![],
+            member_hooks: vec![],
+            channel_hooks: vec![],
+            shutdown_hooks: vec![],
             config_values: Default::default(),
             background_service: Default::default(),
-            pool,
+            storage,
             config_cache: ConfigCache::default(),
-            message_cache: TimedCache::new(chrono::Duration::days(7).to_std().unwrap()),
+            message_cache: Cache::new(LruEvictionStrategy::new(MESSAGE_CACHE_GUILDS)),
+            risk_records: TimedCache::new(RISK_CACHE_TTL),
             bot_id_channels: watch::channel(None),
             bot_id_local: Default::default(),
         }
     }
 
+    /// Creates an empty dispatch which keeps all of its state in memory, so it can run without a database.
+    pub fn in_memory(owner: UserId) -> Self {
+        Self::new(owner, Arc::new(MemoryStorage::default()))
+    }
+
     /// Adds a module to this dispatch instance.
     #[instrument(level = "info", skip(self, module), fields(m = % module.info().name))]
     pub fn add_module<T: Module + 'static>(&mut self, module: T) {
@@ -207,6 +247,21 @@ impl Dispatch {
             self.tick_hooks.push(a.clone());
         }
 
+        if inf.on_member {
+            info!("has member hooks");
+            self.member_hooks.push(a.clone());
+        }
+
+        if inf.on_channel_create {
+            info!("has channel creation hook");
+            self.channel_hooks.push(a.clone());
+        }
+
+        if inf.on_shutdown {
+            info!("has shutdown hook");
+            self.shutdown_hooks.push(a.clone());
+        }
+
         for v in &inf.config_values {
             info!("adds config value {}", v.name());
             self.config_values.insert(v.name(), v.clone());
@@ -216,6 +271,17 @@ impl Dispatch {
         self.modules.insert(inf.name, a);
     }
 
+    /// Runs the shutdown hooks of every module. Errors are logged rather than returned so that
+    /// one failing module doesn't stop the others from cleaning up.
+    pub async fn run_shutdown_hooks(&self) {
+        for m in &self.shutdown_hooks {
+            m.on_shutdown(self)
+                .instrument(info_span!("applying shutdown hook", h=%m.info().name))
+                .await
+                .log_error();
+        }
+    }
+
     /// Retrieves a module by name.
     pub fn module(&self, name: &str) -> Option<&dyn Module> {
         self.modules.get(name).map(|r| r.as_ref())
@@ -323,7 +389,7 @@ impl Dispatch {
         let name = cmd_name;
         let cmd_mod = self.command_module(name)?;
         cmd_mod
-            .process(self, ctx, &new_message, command)
+            .process(self, ctx, new_message, command)
             .instrument(info_span!("running command", c=%cmd_mod.info().name))
             .await?;
 
@@ -362,6 +428,36 @@ impl EventHandler for Dispatch {
         debug!("Processing took {:?}", elapsed);
     }
 
+    #[instrument(level = "info", skip(self, ctx, new_member), fields(g = % guild_id, u = % new_member.user.id))]
+    async fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, new_member: Member) {
+        for m in &self.member_hooks {
+            m.on_member_join(self, &ctx, &new_member)
+                .instrument(debug_span!("applying member join hook", h=%m.info().name))
+                .await
+                .log_error();
+        }
+    }
+
+    #[instrument(level = "info", skip(self, ctx, user, member), fields(g = % guild_id, u = % user.id))]
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member: Option<Member>) {
+        for m in &self.member_hooks {
+            m.on_member_leave(self, &ctx, guild_id, &user, member.as_ref())
+                .instrument(debug_span!("applying member leave hook", h=%m.info().name))
+                .await
+                .log_error();
+        }
+    }
+
+    #[instrument(level = "info", skip(self, ctx, channel), fields(g = % channel.guild_id, c = % channel.id))]
+    async fn channel_create(&self, ctx: Context, channel: &GuildChannel) {
+        for m in &self.channel_hooks {
+            m.on_channel_create(self, &ctx, channel)
+                .instrument(debug_span!("applying channel hook", h=%m.info().name))
+                .await
+                .log_error();
+        }
+    }
+
     async fn ready(&self, ctx: Context, rdy: Ready) {
         self.bot_id_channels
             .0
@@ -408,16 +504,28 @@ impl BackgroundService {
 
         while let Some(d) = self.dispatch.upgrade() {
             self.process_events(&d).await.log_error();
+            self.run_tick_hooks(&d).await;
+            d.sweep_caches();
             std::mem::drop(d); // Manually drop to avoid holding while we wait.
             interval.tick().await;
         }
     }
 
+    /// Runs the tick hooks of every module.
+    pub async fn run_tick_hooks(&self, dis: &Dispatch) {
+        for m in &dis.tick_hooks {
+            m.on_tick(dis, &self.ctx)
+                .instrument(debug_span!("applying tick hook", h=%m.info().name))
+                .await
+                .log_error();
+        }
+    }
+
     /// Processes timed events from the database.
     #[instrument(level = "info", skip(self, dis))]
     pub async fn process_events(&self, dis: &Dispatch) -> crate::error::Result<()> {
         let mut batch =
-            TimedEvents::get_actions_before(dis.pool(), chrono::DateTime::from(chrono::Local::now())).await?;
+            TimedEvents::get_actions_before(dis.storage(), chrono::DateTime::from(chrono::Local::now())).await?;
 
         // Avoid a long sequence of the same guild from bulk actions
         batch.shuffle(&mut thread_rng());
@@ -459,4 +567,16 @@ impl EventHandler for ArcDispatch {
     async fn ready(&self, ctx: Context, rdy: Ready) {
         self.0.ready(ctx, rdy).await
     }
+
+    async fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, new_member: Member) {
+        self.0.guild_member_addition(ctx, guild_id, new_member).await
+    }
+
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member: Option<Member>) {
+        self.0.guild_member_removal(ctx, guild_id, user, member).await
+    }
+
+    async fn channel_create(&self, ctx: Context, channel: &GuildChannel) {
+        self.0.channel_create(ctx, channel).await
+    }
 }
@@ -18,6 +18,7 @@ extern crate tracing;
 use clap::{AppSettings, SubCommand};
 #[cfg(target_env = "gnu")]
 use jemallocator::Jemalloc;
+#[allow(deprecated)]
 use std::panic::PanicInfo;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
@@ -32,6 +33,7 @@ fn main() -> glimbot::error::Result<()> {
     better_panic::install();
 
     let pre_hook = std::panic::take_hook();
+    #[allow(deprecated)]
     let hook = move |p: &PanicInfo<'_>| {
         if let Err(e) = glimbot::run::PANIC_ALERT_CHANNEL.0.send(()) {
             error!("Unable to alert panic watchdog of failure because {}. Aborting...", e);
@@ -29,11 +29,14 @@ impl Module for BaseFilter {
             ModInfo::with_name("base-filter", "")
                 .with_filter(true)
                 .with_sensitivity(Sensitivity::Low)
-                .with_config_value(config::Value::<char>::with_default(
-                    "command_prefix",
-                    "A single character which will precede commands.",
-                    || '!',
-                ))
+                .with_config_value(
+                    config::Value::<char>::with_default(
+                        "command_prefix",
+                        "A single character which will precede commands.",
+                        || '!',
+                    )
+                    .with_example("?"),
+                )
         });
         &INFO
     }
@@ -1,33 +1,253 @@
 //! Contains the `config` command module for updating per-guild config values.
 
-use itertools::Itertools;
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
 use once_cell::sync::Lazy;
 use serenity::client::Context;
+use serenity::http::AttachmentType;
 use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use serenity::model::misc::Mentionable;
 use serenity::utils::{content_safe, ContentSafeOptions, MessageBuilder};
+use shrinkwraprs::Shrinkwrap;
 use structopt::StructOpt;
 
+pub use crate::db::storage::ConfigChange;
 use crate::db::DbContext;
+use crate::dispatch::config::{ConfigPath, FromStrWithCtx, VerifiedRole};
 use crate::dispatch::Dispatch;
+use crate::error::IntoBotErr;
+use crate::module::confirm::{await_confirmation, DEFAULT_CONFIRMATION_TIMEOUT};
+use crate::module::roles::JoinableRoles;
 use crate::module::{ModInfo, Module, Sensitivity};
 use crate::util::ClapExt;
+
 /// Module to allow setting configuration values for a guild.
 pub struct ConfigModule;
 
+/// The number of changes shown by `config history`.
+pub const HISTORY_LENGTH: i64 = 10;
+/// The longest a value is allowed to be in `config history` before it's truncated.
+const HISTORY_VALUE_LENGTH: usize = 60;
+
+/// The largest config document `config import` will accept.
+pub const MAX_IMPORT_BYTES: u64 = 256 * 1024;
+/// The most changes listed individually when confirming an import.
+const MAX_IMPORT_DIFF_LINES: usize = 20;
+
+impl_err!(NoSuchVersion, "That config key has no such version.", true);
+impl_err!(
+    FieldOfUnsetValue,
+    "That config value isn't set and has no default; set the whole value first.",
+    true
+);
+impl_err!(
+    NotClearable,
+    "That config value can't be unset; set it to something else instead.",
+    true
+);
+impl_err!(
+    NoDefault,
+    "That config value has no default to reset to; use config unset instead.",
+    true
+);
+impl_err!(
+    NoImportAttachment,
+    "Attach the config document to import to your message.",
+    true
+);
+impl_err!(ImportTooLarge, "That config document is too large to import.", true);
+impl_err!(
+    NothingToRevert,
+    "That config key has no earlier value to revert to.",
+    true
+);
+
+/// Wrapper around DbContext to retrieve the history of config values.
+#[derive(Shrinkwrap)]
+pub struct ConfigHistory<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> ConfigHistory<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        ConfigHistory {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves the most recent changes to a config value, newest first.
+    pub async fn recent(&self, key: &str, limit: i64) -> crate::error::Result<Vec<ConfigChange>> {
+        self.ctx.storage().config_history(self.ctx.guild(), key, limit).await
+    }
+
+    /// Retrieves a specific version of a config value, if it exists.
+    pub async fn version(&self, key: &str, version: i32) -> crate::error::Result<Option<ConfigChange>> {
+        self.ctx.storage().config_version(self.ctx.guild(), key, version).await
+    }
+}
+
+/// A guild's whole configuration, as produced by `config export` and consumed by `config import`.
+/// Values are stored in the same form `config set` accepts.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ConfigDocument {
+    /// Config values which are set, by key.
+    #[serde(default)]
+    pub config: BTreeMap<String, String>,
+    /// The roles users may join themselves. If absent, importing leaves joinable roles alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joinable_roles: Option<Vec<String>>,
+}
+
+impl ConfigDocument {
+    /// Collects every config value which is set in a guild, along with its joinable roles.
+    pub async fn export(dis: &Dispatch, gid: GuildId) -> crate::error::Result<Self> {
+        let db = dis.db(gid);
+        let mut config = BTreeMap::new();
+        for (key, val) in dis.config_values() {
+            if let Some(v) = val.get_json(&db).await? {
+                config.insert(key.to_string(), val.display_value(v)?);
+            }
+        }
+
+        let roles = JoinableRoles::new(&db).joinable_roles().await?;
+        Ok(ConfigDocument {
+            config,
+            joinable_roles: Some(roles.into_iter().map(|r| r.to_string()).collect()),
+        })
+    }
+}
+
+/// A validated config value from an imported document which differs from the current value.
+struct ImportedValue {
+    /// The config key.
+    key: String,
+    /// The current value, if set.
+    old: Option<serde_json::Value>,
+    /// The validated new value.
+    new: serde_json::Value,
+}
+
+/// Imports a config document, showing the changes and applying them all at once if the author confirms.
+async fn import_config(dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<String> {
+    let gid = orig.guild_id.unwrap();
+    let attachment = orig.attachments.first().ok_or(NoImportAttachment)?;
+    if attachment.size > MAX_IMPORT_BYTES {
+        return Err(ImportTooLarge.into());
+    }
+    let doc: ConfigDocument = serde_json::from_slice(&attachment.download().await?).into_user_err()?;
+
+    let db = dis.db(gid).with_actor(orig.author.id);
+    let mut changes = Vec::new();
+    for (key, s) in &doc.config {
+        let val = dis.config_value(key)?;
+        let new = val.validate(ctx, gid, s).await?;
+        let old = val.get_json(&db).await?;
+        if old.as_ref() != Some(&new) {
+            changes.push(ImportedValue {
+                key: key.clone(),
+                old,
+                new,
+            });
+        }
+    }
+
+    let joinable = JoinableRoles::new(&db);
+    let current_roles = joinable.joinable_roles().await?;
+    let mut new_roles = None;
+    if let Some(roles) = &doc.joinable_roles {
+        let mut out = Vec::with_capacity(roles.len());
+        for r in roles {
+            out.push(VerifiedRole::from_str_with_ctx(r, ctx, gid).await?.into_inner());
+        }
+        out.sort_unstable();
+        out.dedup();
+        if out != current_roles {
+            new_roles = Some(out);
+        }
+    }
+
+    if changes.is_empty() && new_roles.is_none() {
+        return Ok("The imported config matches the current config; nothing to change.".to_string());
+    }
+
+    let mut summary = vec![format!("Importing {} config value(s):", changes.len())];
+    summary.extend(changes.iter().take(MAX_IMPORT_DIFF_LINES).map(|c| {
+        format!(
+            "{}: {} -> {}",
+            c.key,
+            summarize_value(&c.old),
+            summarize_value(&Some(c.new.clone()))
+        )
+    }));
+    if changes.len() > MAX_IMPORT_DIFF_LINES {
+        summary.push(format!("... and {} more", changes.len() - MAX_IMPORT_DIFF_LINES));
+    }
+    if let Some(roles) = &new_roles {
+        let added = roles.iter().filter(|r| !current_roles.contains(r)).count();
+        let removed = current_roles.iter().filter(|r| !roles.contains(r)).count();
+        summary.push(format!("joinable roles: {} added, {} removed", added, removed));
+    }
+    let summary = content_safe(
+        ctx,
+        summary.join("\n"),
+        &ContentSafeOptions::default().display_as_member_from(gid),
+    )
+    .await;
+
+    if !await_confirmation(ctx, orig, summary, DEFAULT_CONFIRMATION_TIMEOUT).await? {
+        return Ok("Import cancelled.".to_string());
+    }
+
+    let values: Vec<_> = changes.iter().map(|c| (c.key.clone(), c.new.clone())).collect();
+    db.storage()
+        .import_config(gid, &values, new_roles.as_deref(), db.actor())
+        .await?;
+
+    for c in changes.iter() {
+        dis.config_value(&c.key)?.cache_json(c.new.clone(), &db).await?;
+    }
+
+    Ok(format!(
+        "Imported {} config value(s){}.",
+        changes.len(),
+        if new_roles.is_some() { " and joinable roles" } else { "" }
+    ))
+}
+
+/// Renders a JSON value on one line for the config history, truncating long values.
+fn summarize_value(v: &Option<serde_json::Value>) -> String {
+    let s = match v {
+        None => return "<unset>".to_string(),
+        Some(v) => v.to_string(),
+    };
+    if s.chars().count() > HISTORY_VALUE_LENGTH {
+        let mut out: String = s.chars().take(HISTORY_VALUE_LENGTH).collect();
+        out.push('…');
+        out
+    } else {
+        s
+    }
+}
+
 /// Command to set bot config values for this guild.
 #[derive(Debug, StructOpt)]
 #[structopt(name = "config", no_version)]
 enum ConfigOpt {
     /// Sets a bot config value
     Set {
-        /// The name of the config value to set
+        /// The name of the config value to set. Fields of JSON values can be set with a dotted path, i.e.
+        /// `spam_config.max_pressure`
         key: String,
         /// The value to set it to
         value: String,
     },
     /// Shows a bot config value
     Show {
-        /// The name of the config value to show
+        /// The name of the config value to show, or a dotted path to a field of a JSON value
         key: String,
     },
     /// Lists the available config values to be set.
@@ -37,6 +257,32 @@ enum ConfigOpt {
         /// The name of the config value to show
         key: String,
     },
+    /// Shows recent changes to a config value
+    History {
+        /// The name of the config value to show the history of
+        key: String,
+    },
+    /// Reverts a config value to an earlier version
+    Revert {
+        /// The name of the config value to revert
+        key: String,
+        /// The version to revert to, as shown by `config history`. Defaults to the value before the latest change.
+        version: Option<i32>,
+    },
+    /// Unsets a bot config value, if it's allowed to be unset
+    Unset {
+        /// The name of the config value to unset
+        key: String,
+    },
+    /// Resets a bot config value to its default
+    Reset {
+        /// The name of the config value to reset
+        key: String,
+    },
+    /// Exports every config value which is set, along with joinable roles, as a JSON document
+    Export,
+    /// Imports a JSON document from `config export`, attached to the message, after confirming the changes
+    Import,
 }
 
 #[async_trait::async_trait]
@@ -62,27 +308,156 @@ impl Module for ConfigModule {
         let gid = orig.guild_id.unwrap();
         let message = match opts {
             ConfigOpt::Set { key, value } => {
-                let config_val = dis.config_value(&key)?;
-                let new_val = config_val.validate(ctx, orig.guild_id.unwrap(), &value).await?;
-                let ctx = dis.db(gid);
-                config_val.insert_json(new_val, &ctx).await?;
+                let path = ConfigPath::parse(&key);
+                let config_val = dis.config_value(path.key)?;
+                let db = dis.db(gid).with_actor(orig.author.id);
+                let new_val = if path.is_field() {
+                    let mut merged = match config_val.get_json(&db).await? {
+                        Some(v) => v,
+                        None => config_val.default_json()?.ok_or(FieldOfUnsetValue)?,
+                    };
+                    path.set(&mut merged, ConfigPath::parse_field_value(&value))?;
+                    let validated = config_val.validate(ctx, gid, &merged.to_string()).await?;
+                    // Unknown fields are dropped during validation rather than rejected, so make sure it survived.
+                    path.get(&validated)?;
+                    validated
+                } else {
+                    config_val.validate(ctx, gid, &value).await?
+                };
+                config_val.insert_json(new_val, &db).await?;
                 format!("Set {} to specified value.", &key)
             }
             ConfigOpt::Show { key } => {
-                let config_val = dis.config_value(&key)?;
+                let path = ConfigPath::parse(&key);
+                let config_val = dis.config_value(path.key)?;
                 let db = DbContext::new(dis, gid);
                 let val: Option<serde_json::Value> = config_val.get_json(&db).await?;
 
                 match val {
                     None => "<unset>".to_string(),
+                    Some(v) if path.is_field() => serde_json::to_string_pretty(path.get(&v)?)?,
                     Some(v) => config_val.display_value(v)?,
                 }
             }
-            ConfigOpt::List => dis.config_values().keys().join(", "),
+            ConfigOpt::List => {
+                let mut out = Vec::new();
+                for (key, v) in dis.config_values() {
+                    out.push(format!("{} ({})", key, v.schema()?.type_name));
+                }
+                out.join("\n")
+            }
             ConfigOpt::Info { key } => {
                 let config_val = dis.config_value(&key)?;
-                format!("{}: {}", key, config_val.help())
+                let schema = config_val.schema()?;
+                let mut out = format!("{}: {}\nType: {}", key, config_val.help(), schema.type_name);
+                if let Some(d) = schema.default {
+                    out.push_str(&format!("\nDefault: {}", d));
+                }
+                if let Some(e) = schema.example {
+                    out.push_str(&format!("\nExample: {}", e));
+                }
+                if !schema.constraints.is_empty() {
+                    out.push_str("\nConstraints:");
+                    for c in schema.constraints {
+                        out.push_str(&format!("\n  {}: {}", c.field_name(), c));
+                    }
+                }
+                out
+            }
+            ConfigOpt::History { key } => {
+                let config_val = dis.config_value(&key)?;
+                let changes = ConfigHistory::new(dis.db(gid))
+                    .recent(config_val.name(), HISTORY_LENGTH)
+                    .await?;
+                if changes.is_empty() {
+                    format!("No recorded changes to {}.", key)
+                } else {
+                    let mut out = String::new();
+                    for c in changes {
+                        let who = c
+                            .changed_by
+                            .map(|u| u.mention().to_string())
+                            .unwrap_or_else(|| "Glimbot".to_string());
+                        out.push_str(&format!(
+                            "v{} {} by {}\n  {} -> {}\n",
+                            c.version,
+                            c.changed_at.format("%Y-%m-%d %H:%M UTC"),
+                            who,
+                            summarize_value(&c.old_value),
+                            summarize_value(&c.new_value)
+                        ));
+                    }
+                    out
+                }
+            }
+            ConfigOpt::Revert { key, version } => {
+                let config_val = dis.config_value(&key)?;
+                let db = dis.db(gid).with_actor(orig.author.id);
+                let history = ConfigHistory::new(&db);
+                let target = match version {
+                    Some(v) => {
+                        history
+                            .version(config_val.name(), v)
+                            .await?
+                            .ok_or(NoSuchVersion)?
+                            .new_value
+                    }
+                    None => {
+                        history
+                            .recent(config_val.name(), 1)
+                            .await?
+                            .into_iter()
+                            .next()
+                            .ok_or(NothingToRevert)?
+                            .old_value
+                    }
+                };
+                match target {
+                    Some(v) => {
+                        // The value's constraints may have changed since, so check it as if it were being set anew.
+                        let v = config_val.validate(ctx, gid, &config_val.display_value(v)?).await?;
+                        config_val.insert_json(v, &db).await?
+                    }
+                    // The value was unset at that version.
+                    None if config_val.clearable() || config_val.has_default() => config_val.remove(&db).await?,
+                    None => return Err(NotClearable.into()),
+                }
+                match version {
+                    Some(v) => format!("Reverted {} to version {}.", key, v),
+                    None => format!("Reverted {} to its previous value.", key),
+                }
+            }
+            ConfigOpt::Unset { key } => {
+                let config_val = dis.config_value(&key)?;
+                if !config_val.clearable() {
+                    return Err(NotClearable.into());
+                }
+                config_val.remove(&dis.db(gid).with_actor(orig.author.id)).await?;
+                format!("Unset {}.", key)
+            }
+            ConfigOpt::Reset { key } => {
+                let config_val = dis.config_value(&key)?;
+                if !config_val.has_default() {
+                    return Err(NoDefault.into());
+                }
+                config_val.remove(&dis.db(gid).with_actor(orig.author.id)).await?;
+                format!("Reset {} to its default.", key)
+            }
+            ConfigOpt::Export => {
+                let doc = ConfigDocument::export(dis, gid).await?;
+                let file = AttachmentType::Bytes {
+                    data: serde_json::to_vec_pretty(&doc)?.into(),
+                    filename: format!("config-{}.json", gid),
+                };
+                orig.channel_id
+                    .send_files(ctx, vec![file], |m| {
+                        m.reference_message(orig)
+                            .content(format!("Exported {} config value(s).", doc.config.len()))
+                    })
+                    .await?;
+                return Ok(());
             }
+            ConfigOpt::Import => import_config(dis, ctx, orig).await?,
         };
 
         let message = content_safe(ctx, message, &ContentSafeOptions::default().display_as_member_from(gid)).await;
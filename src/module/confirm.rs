@@ -0,0 +1,162 @@
+//! Contains logic for asking the invoking user to confirm an action before it is taken, as well as
+//! the `confirm_commands` config value controlling which commands require confirmation.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{Message, ReactionType};
+use serenity::utils::MessageBuilder;
+
+use crate::dispatch::config::Value;
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity, CHECKMARK_IN_GREEN_BOX};
+
+/// Reaction used to cancel an action.
+pub const CROSS_MARK: char = '❌';
+/// How long to wait for a user to confirm an action.
+pub const DEFAULT_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Config key for the commands which require confirmation before running.
+pub const CONFIRM_COMMANDS: &str = "confirm_commands";
+/// The commands which require confirmation if `confirm_commands` hasn't been set.
+pub const DEFAULT_CONFIRM_COMMANDS: &[&str] = &["mod ban", "spam clean", "shutdown"];
+
+/// Registers the `confirm_commands` config value.
+pub struct ConfirmationModule;
+
+/// A list of commands, possibly including subcommands, i.e. "mod ban".
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CommandList(Vec<String>);
+
+impl CommandList {
+    /// Normalizes a command name so that whitespace and case differences don't matter.
+    fn normalize(s: &str) -> String {
+        s.split_whitespace().join(" ").to_lowercase()
+    }
+
+    /// Returns true if the command is in this list.
+    pub fn contains(&self, command: &str) -> bool {
+        let command = Self::normalize(command);
+        self.0.contains(&command)
+    }
+}
+
+impl Default for CommandList {
+    fn default() -> Self {
+        Self(DEFAULT_CONFIRM_COMMANDS.iter().map(|c| c.to_string()).collect())
+    }
+}
+
+impl FromStr for CommandList {
+    type Err = Infallible;
+
+    /// Parses a comma separated list of commands. `<none>`, as displayed for an empty list, parses as an empty list.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "<none>" {
+            return Ok(Self(Vec::new()));
+        }
+        Ok(Self(
+            s.split(',')
+                .map(Self::normalize)
+                .filter(|c| !c.is_empty())
+                .unique()
+                .collect(),
+        ))
+    }
+}
+
+impl fmt::Display for CommandList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<none>");
+        }
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ConfirmationModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("confirmation", "asks for confirmation before running sensitive commands.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_config_value(Value::<CommandList>::with_default(
+                    CONFIRM_COMMANDS,
+                    "A comma separated list of commands which require the invoking user to confirm them with a reaction, i.e. \"mod ban, spam clean\".",
+                    Default::default,
+                )
+                .with_example("mod ban, spam clean"))
+        });
+        &INFO
+    }
+}
+
+/// Asks the author of the original message to confirm the command if the guild requires it.
+/// `command` is the name of the command including any subcommand, i.e. "mod ban".
+/// Returns true if the command should go ahead.
+pub async fn confirm_if_required(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    command: &str,
+    summary: impl fmt::Display,
+) -> crate::error::Result<bool> {
+    let gid = orig.guild_id.unwrap();
+    let required = dis
+        .config_value_t::<CommandList>(CONFIRM_COMMANDS)?
+        .get_or_default(&dis.db(gid))
+        .await?
+        .contains(command);
+
+    if !required {
+        return Ok(true);
+    }
+
+    await_confirmation(ctx, orig, summary, DEFAULT_CONFIRMATION_TIMEOUT).await
+}
+
+/// Posts a summary of an action in reply to the original message and waits for the author to react
+/// with ✅ or ❌. Returns true only if the author confirmed before the timeout.
+pub async fn await_confirmation(
+    ctx: &Context,
+    orig: &Message,
+    summary: impl fmt::Display,
+    timeout: Duration,
+) -> crate::error::Result<bool> {
+    let prompt = MessageBuilder::new()
+        .push_codeblock_safe(summary, None)
+        .push(format!(
+            "React with {} to confirm or {} to cancel.",
+            CHECKMARK_IN_GREEN_BOX, CROSS_MARK
+        ))
+        .build();
+    let prompt = orig.reply(ctx, prompt).await?;
+    prompt.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
+    prompt.react(ctx, CROSS_MARK).await?;
+
+    let is_answer =
+        |r: &ReactionType| r.unicode_eq(&CHECKMARK_IN_GREEN_BOX.to_string()) || r.unicode_eq(&CROSS_MARK.to_string());
+    let reaction = prompt
+        .await_reaction(ctx)
+        .author_id(orig.author.id)
+        .filter(move |r| is_answer(&r.emoji))
+        .timeout(timeout)
+        .await;
+
+    let confirmed = reaction
+        .map(|r| r.as_inner_ref().emoji.unicode_eq(&CHECKMARK_IN_GREEN_BOX.to_string()))
+        .unwrap_or(false);
+
+    if !confirmed {
+        debug!("action was not confirmed");
+        prompt.reply(ctx, "Cancelled.").await?;
+    }
+
+    Ok(confirmed)
+}
@@ -7,21 +7,29 @@ use std::fmt::Formatter;
 use std::sync::Arc;
 
 use serenity::client::Context;
-use serenity::model::channel::Message;
+use serenity::model::channel::{GuildChannel, Message};
+use serenity::model::guild::Member;
+use serenity::model::id::GuildId;
+use serenity::model::user::User;
 
 use crate::dispatch::{config, Dispatch};
 
 pub mod base_filter;
 pub mod conf;
+pub mod confirm;
 pub mod info;
 pub mod mock_raid;
 pub mod moderation;
 pub mod owner;
 pub mod privilege;
+pub mod risk;
 pub mod roles;
 pub mod shutdown;
+pub mod slowmode;
 pub mod spam;
 pub mod status;
+pub mod sticky;
+pub mod whois;
 
 pub const CHECKMARK_IN_GREEN_BOX: char = '✅';
 
@@ -96,6 +104,12 @@ pub struct ModInfo {
     pub on_tick: bool,
     /// Whether or not this message has an on_message hook.
     pub on_message: bool,
+    /// Whether or not this module has on_member_join and on_member_leave hooks.
+    pub on_member: bool,
+    /// Whether or not this module has an on_channel_create hook.
+    pub on_channel_create: bool,
+    /// Whether or not this module has an on_shutdown hook.
+    pub on_shutdown: bool,
     /// A short help message about the command.
     pub short_desc: &'static str,
 }
@@ -111,6 +125,9 @@ impl ModInfo {
             config_values: Vec::new(),
             on_tick: false,
             on_message: false,
+            on_member: false,
+            on_channel_create: false,
+            on_shutdown: false,
             short_desc: desc,
         }
     }
@@ -150,6 +167,24 @@ impl ModInfo {
         self.on_message = with_hook;
         self
     }
+
+    /// Specifies whether or not this module has hooks that run when members join or leave a guild.
+    pub fn with_member_hooks(mut self, with_hooks: bool) -> Self {
+        self.on_member = with_hooks;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a channel is created.
+    pub fn with_channel_hook(mut self, with_hook: bool) -> Self {
+        self.on_channel_create = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when Glimbot shuts down.
+    pub fn with_shutdown_hook(mut self, with_hook: bool) -> Self {
+        self.on_shutdown = with_hook;
+        self
+    }
 }
 
 impl_err!(UnimplementedModule, "This module hasn't been finished yet.", true);
@@ -195,4 +230,37 @@ pub trait Module: Sync + Send {
     async fn on_message(&self, _dis: &Dispatch, _ctx: &Context, _orig: &Message) -> crate::error::Result<()> {
         Err(UnimplementedModule.into())
     }
+
+    /// Hook to run when a member joins a guild.
+    async fn on_member_join(&self, _dis: &Dispatch, _ctx: &Context, _member: &Member) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a member leaves a guild. The member information is only available if
+    /// the member was in the cache.
+    async fn on_member_leave(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _guild: GuildId,
+        _user: &User,
+        _member: Option<&Member>,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a channel is created in a guild.
+    async fn on_channel_create(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _channel: &GuildChannel,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run once the shards have stopped, i.e. to persist in-memory state.
+    async fn on_shutdown(&self, _dis: &Dispatch) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
 }
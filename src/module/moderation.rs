@@ -3,21 +3,28 @@
 
 use std::borrow::{Borrow, Cow};
 
+use chrono::Utc;
 use humantime::Duration;
 use once_cell::sync::Lazy;
 use serenity::builder::CreateEmbed;
 use serenity::client::Context;
-use serenity::model::channel::Message;
+use serenity::http::AttachmentType;
+use serenity::model::channel::{ChannelType, GuildChannel, Message, PermissionOverwrite, PermissionOverwriteType};
 use serenity::model::guild::Member;
-use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
 use serenity::model::misc::Mentionable;
-use serenity::utils::Color;
+use serenity::model::Permissions;
+use serenity::utils::{Color, MessageBuilder};
 use structopt::StructOpt;
 
 use crate::db::timed::{Action, ONE_HUNDREDISH_YEARS};
 use crate::db::DbContext;
-use crate::dispatch::config::{FromStrWithCtx, Value, VerifiedChannel, VerifiedRole, VerifiedUser};
+use crate::dispatch::config::{FromStrWithCtx, RoleExt, Value, VerifiedChannel, VerifiedRole, VerifiedUser};
 use crate::dispatch::Dispatch;
+use crate::error::{GuildNotInCache, LogErrorExt};
+use crate::module::confirm::{await_confirmation, confirm_if_required, DEFAULT_CONFIRMATION_TIMEOUT};
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::risk::MemberRisk;
 use crate::module::{ModInfo, Module, Sensitivity};
 use crate::util::constraints::AtMostU64;
 use crate::util::ClapExt;
@@ -29,10 +36,41 @@ pub struct ModerationModule;
 /// sane.
 #[derive(Debug, StructOpt)]
 pub struct CommonOpts {
-    /// Which user the action should apply to.
-    user: String,
+    /// Which user the action should apply to. May be omitted if bulk targets are specified.
+    user: Option<String>,
     /// Why the action is being taken.
     reason: Option<String>,
+    #[structopt(flatten)]
+    targets: BulkTargets,
+}
+
+/// Options for applying an action to many users at once, i.e. during a raid.
+#[derive(Debug, StructOpt)]
+pub struct BulkTargets {
+    /// Additional users the action should apply to.
+    #[structopt(long)]
+    users: Vec<String>,
+    /// Applies the action to every member who joined after this time. Accepts a timestamp like
+    /// "2021-04-01T12:00:00Z" or a duration before now like "30m".
+    #[structopt(long, parse(try_from_str = crate::util::parse_time_or_ago))]
+    joined_after: Option<chrono::DateTime<Utc>>,
+    /// Applies the action to every author of a message Glimbot has seen since this time.
+    /// Accepts the same formats as --joined-after.
+    #[structopt(long, parse(try_from_str = crate::util::parse_time_or_ago))]
+    from_message_authors_since: Option<chrono::DateTime<Utc>>,
+    /// Only considers messages in this channel for --from-message-authors-since.
+    #[structopt(long, requires = "from-message-authors-since")]
+    channel: Option<String>,
+    /// Lists the users the action would apply to without taking it.
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+impl BulkTargets {
+    /// Returns true if any option targeting more than one user was specified.
+    pub fn is_bulk(&self) -> bool {
+        !self.users.is_empty() || self.joined_after.is_some() || self.from_message_authors_since.is_some()
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -44,7 +82,6 @@ pub enum ModOpt {
     Kick(CommonOpts),
     /// Ban a user from the server.
     Ban {
-        ///
         #[structopt(flatten)]
         common: CommonOpts,
         /// How long the user should be banned for. Specified in human format, i.e. "5d 2h 5m"
@@ -60,7 +97,6 @@ pub enum ModOpt {
     SoftBan(CommonOpts),
     /// Adds the muted user role to a user.
     Mute {
-        ///
         #[structopt(flatten)]
         common: CommonOpts,
         #[structopt(short = "d")]
@@ -68,29 +104,49 @@ pub enum ModOpt {
         /// Max 100 years, min 1 minute. Very large values may be interpreted as indefinite in duration.
         duration: Option<humantime::Duration>,
     },
+    /// Sets up the mute role, creating it if necessary, and denies it send permissions in every channel.
+    SetupMute {
+        /// An existing role to adopt as the mute role. If unspecified, the current mute role is used,
+        /// or a new one is created.
+        role: Option<String>,
+    },
 }
 
 impl ModOpt {
-    /// Retrieves the [`CommonOpts`] from each variant.
-    pub fn common_args(&self) -> &CommonOpts {
+    /// Retrieves the [`CommonOpts`] from each variant which acts on a user.
+    pub fn common_args(&self) -> Option<&CommonOpts> {
         match self {
-            ModOpt::Warn(c) => c,
-            ModOpt::Kick(c) => c,
-            ModOpt::Ban { common, .. } => common,
-            ModOpt::SoftBan(c) => c,
-            ModOpt::Mute { common, .. } => common,
+            ModOpt::Warn(c) => Some(c),
+            ModOpt::Kick(c) => Some(c),
+            ModOpt::Ban { common, .. } => Some(common),
+            ModOpt::SoftBan(c) => Some(c),
+            ModOpt::Mute { common, .. } => Some(common),
+            ModOpt::SetupMute { .. } => None,
         }
     }
 
-    /// Retrieves the [`ActionKind`] which matches this variant.
-    pub fn kind(&self) -> ActionKind {
+    /// Retrieves the [`ActionKind`] which matches this variant, if it acts on a user.
+    pub fn kind(&self) -> Option<ActionKind> {
         use ActionKind::*;
         match self {
-            ModOpt::Warn(_) => Warn,
-            ModOpt::Kick(_) => Kick,
-            ModOpt::Ban { .. } => Ban,
-            ModOpt::SoftBan(_) => SoftBan,
-            ModOpt::Mute { .. } => Mute,
+            ModOpt::Warn(_) => Some(Warn),
+            ModOpt::Kick(_) => Some(Kick),
+            ModOpt::Ban { .. } => Some(Ban),
+            ModOpt::SoftBan(_) => Some(SoftBan),
+            ModOpt::Mute { .. } => Some(Mute),
+            ModOpt::SetupMute { .. } => None,
+        }
+    }
+
+    /// Retrieves the name of the subcommand, as it would be typed by a user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModOpt::Warn(_) => "warn",
+            ModOpt::Kick(_) => "kick",
+            ModOpt::Ban { .. } => "ban",
+            ModOpt::SoftBan(_) => "soft-ban",
+            ModOpt::Mute { .. } => "mute",
+            ModOpt::SetupMute { .. } => "setup-mute",
         }
     }
 
@@ -116,6 +172,16 @@ pub const MOD_CHANNEL: &str = "mod_log_channel";
 /// Config key for the mute role, which should be assigned to users to prevent them from sending
 /// messages.
 pub const MUTE_ROLE: &str = "mute_role";
+/// The delay between each action taken against a user during a bulk action.
+pub const BULK_ACTION_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+/// The maximum length of a list of users in a bulk action preview or mod log entry.
+pub const MAX_USER_LIST_LEN: usize = 1000;
+/// The name of the mute role created by `mod setup-mute`.
+pub const DEFAULT_MUTE_ROLE_NAME: &str = "Muted";
+/// The permissions denied to the mute role in each channel.
+pub const MUTE_DENIED_PERMISSIONS: Permissions = Permissions::from_bits_truncate(
+    Permissions::SEND_MESSAGES.bits() | Permissions::ADD_REACTIONS.bits() | Permissions::SPEAK.bits(),
+);
 
 #[async_trait::async_trait]
 impl Module for ModerationModule {
@@ -125,11 +191,14 @@ impl Module for ModerationModule {
             ModInfo::with_name("mod", "allows moderators to kick/warn/ban/etc users.")
                 .with_sensitivity(Sensitivity::High)
                 .with_command(true)
-                .with_config_value(Value::<VerifiedChannel>::new(
-                    MOD_CHANNEL,
-                    "Channel for logging moderation actions.",
-                ))
-                .with_config_value(Value::<VerifiedRole>::new(MUTE_ROLE, "Role to assign to muted users."))
+                .with_config_value(
+                    Value::<VerifiedChannel>::new(MOD_CHANNEL, "Channel for logging moderation actions.")
+                        .with_example("mod-log"),
+                )
+                .with_config_value(
+                    Value::<VerifiedRole>::new(MUTE_ROLE, "Role to assign to muted users.").with_example("Muted"),
+                )
+                .with_channel_hook(true)
         });
 
         &INFO
@@ -144,33 +213,325 @@ impl Module for ModerationModule {
     ) -> crate::error::Result<()> {
         let gid = orig.guild_id.unwrap();
         let opts = ModOpt::from_iter_with_help(command)?;
-        let common = opts.common_args();
-        let kind = opts.kind();
-        let orig_mess = orig.message_reference.as_ref().map(|m| m.message_id).flatten();
+
+        let (common, kind) = match (opts.common_args(), opts.kind()) {
+            (Some(common), Some(kind)) => (common, kind),
+            _ => {
+                if let ModOpt::SetupMute { role } = &opts {
+                    let setup = setup_mute_role(dis, ctx, gid, role.as_deref()).await?;
+                    let role_name = setup.role.into_inner().to_role_name_or_id(ctx, gid).await;
+                    let mut msg = format!(
+                        "Mute role {} denied send permissions in {} channel(s).",
+                        role_name, setup.updated
+                    );
+                    if !setup.failed.is_empty() {
+                        msg.push_str(&format!(
+                            "\nCouldn't update {} channel(s), check my permissions in them:\n{}",
+                            setup.failed.len(),
+                            format_channel_list(&setup.failed, MAX_USER_LIST_LEN)
+                        ));
+                    }
+                    let msg = MessageBuilder::new().push_codeblock_safe(msg, None).build();
+                    orig.reply(ctx, msg).await?;
+                }
+                return Ok(());
+            }
+        };
+        let orig_mess = orig.message_reference.as_ref().and_then(|m| m.message_id);
         let duration = opts.duration();
         let channel = orig.channel_id;
 
-        let user = VerifiedUser::from_str_with_ctx(&common.user, ctx, gid).await?;
-        let member = gid.member(ctx, user.into_inner()).await?;
+        let make_action = |member: &Member| {
+            let mut action = ModAction::new(member, channel, orig.author.id, kind).with_duration(duration);
 
-        let mut action = ModAction::new(&member, channel, orig.author.id, kind).with_duration(duration);
+            if let Some(m) = orig_mess {
+                action = action.with_original_message(m);
+            }
+
+            if let Some(r) = common.reason.clone() {
+                action = action.with_reason(r);
+            }
+            action
+        };
+
+        if !common.targets.is_bulk() && !common.targets.dry_run {
+            let user = common.user.as_ref().ok_or(NoTargets)?;
+            let user = VerifiedUser::from_str_with_ctx(user, ctx, gid).await?;
+            let member = gid.member(ctx, user.into_inner()).await?;
+            let action = make_action(&member);
+
+            let summary = format!(
+                "{} {} ({}): {}",
+                kind.title_name(),
+                member.display_name(),
+                member.user.id,
+                action.reason()
+            );
+            let command = format!("mod {}", opts.name());
+            if !confirm_if_required(dis, ctx, orig, &command, summary).await? {
+                return Ok(());
+            }
+
+            action.act(dis, ctx).await?;
+            action.report_action(dis, ctx).await?;
+            orig.react(ctx, '✅').await?;
+            return Ok(());
+        }
 
-        if let Some(m) = orig_mess {
-            action = action.with_original_message(m);
+        let members = resolve_bulk_targets(dis, ctx, orig, common).await?;
+        let summary = format!(
+            "{} {} user(s):\n{}",
+            kind.title_name(),
+            members.len(),
+            format_member_list(&members, MAX_USER_LIST_LEN)
+        );
+
+        if common.targets.dry_run {
+            let msg = MessageBuilder::new()
+                .push_codeblock_safe(format!("Dry run. {}", summary), None)
+                .build();
+            orig.reply(ctx, msg).await?;
+            return Ok(());
         }
 
-        if let Some(r) = common.reason.clone() {
-            action = action.with_reason(r);
+        if members.is_empty() {
+            return Err(NoTargets.into());
         }
 
-        action.act(dis, ctx).await?;
-        action.report_action(dis, ctx).await?;
-        orig.react(ctx, '✅').await?;
+        if !await_confirmation(ctx, orig, &summary, DEFAULT_CONFIRMATION_TIMEOUT).await? {
+            return Ok(());
+        }
+
+        let mut succeeded = Vec::with_capacity(members.len());
+        for (i, m) in members.iter().enumerate() {
+            // Stay well clear of Discord's rate limits during large raids.
+            if i > 0 {
+                tokio::time::sleep(BULK_ACTION_DELAY).await;
+            }
+
+            let res = make_action(m).act(dis, ctx).await;
+            res.log_error();
+            if res.is_ok() {
+                succeeded.push(m.clone());
+            }
+        }
+
+        // The actions were already taken, so a missing mod log shouldn't hide how many succeeded.
+        let template = make_action(&members[0]);
+        send_to_mod_log(dis, ctx, gid, |emb| {
+            template.create_bulk_embed(emb, &succeeded, members.len() - succeeded.len());
+            emb
+        })
+        .await
+        .log_error();
+
+        let msg = format!(
+            "{} applied to {} of {} user(s).",
+            kind.title_name(),
+            succeeded.len(),
+            members.len()
+        );
+        orig.reply(ctx, MessageBuilder::new().push_codeblock_safe(msg, None).build())
+            .await?;
+        Ok(())
+    }
 
+    async fn on_channel_create(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        channel: &GuildChannel,
+    ) -> crate::error::Result<()> {
+        let db = dis.db(channel.guild_id);
+        let mute_role = dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?.get(&db).await?;
+        if let Some(r) = mute_role {
+            deny_mute_role_in(ctx, channel, r.into_inner()).await?;
+        }
         Ok(())
     }
 }
 
+/// Resolves the members targeted by the bulk options of a command. The bot, the guild owner, moderators
+/// and the invoking user are never targeted.
+async fn resolve_bulk_targets(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    common: &CommonOpts,
+) -> crate::error::Result<Vec<Member>> {
+    let gid = orig.guild_id.unwrap();
+    let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+    let targets = &common.targets;
+
+    let mut ids = Vec::new();
+    for u in common.user.iter().chain(targets.users.iter()) {
+        ids.push(VerifiedUser::from_str_with_ctx(u, ctx, gid).await?.into_inner());
+    }
+
+    if let Some(t) = targets.joined_after {
+        ids.extend(
+            guild
+                .members
+                .values()
+                .filter(|m| m.joined_at.map_or(false, |j| j > t))
+                .map(|m| m.user.id),
+        );
+    }
+
+    if let Some(t) = targets.from_message_authors_since {
+        let channel = match &targets.channel {
+            Some(c) => Some(VerifiedChannel::from_str_with_ctx(c, ctx, gid).await?),
+            None => None,
+        };
+
+        if let Some(cache) = dis.message_cache().get(&gid) {
+            ids.extend(
+                cache
+                    .snapshot()
+                    .iter()
+                    .filter(|m| m.timestamp >= t)
+                    .filter(|m| channel.map_or(true, |c| m.channel == c.into_inner()))
+                    .map(|m| m.user),
+            );
+        }
+    }
+
+    ids.sort_unstable();
+    ids.dedup();
+
+    let me = dis.bot().await;
+    let mod_role = dis.config_value_t::<VerifiedRole>(PRIV_ROLE)?.get(&dis.db(gid)).await?;
+    let mut out = Vec::with_capacity(ids.len());
+    for id in ids {
+        if id == me || id == guild.owner_id || id == orig.author.id {
+            continue;
+        }
+
+        let member = match guild.members.get(&id) {
+            Some(m) => m.clone(),
+            None => match gid.member(ctx, id).await {
+                Ok(m) => m,
+                Err(_) => continue,
+            },
+        };
+
+        if mod_role
+            .as_ref()
+            .map_or(false, |r| member.roles.contains(&r.into_inner()))
+        {
+            continue;
+        }
+
+        out.push(member);
+    }
+
+    Ok(out)
+}
+
+/// Formats a list of members for display, truncating it if it would be longer than `max_len`.
+pub fn format_member_list(members: &[Member], max_len: usize) -> String {
+    let mut out = String::new();
+    for (i, m) in members.iter().enumerate() {
+        let line = format!("{} ({})\n", m.display_name(), m.user.id);
+        if out.len() + line.len() > max_len {
+            out.push_str(&format!("... and {} more", members.len() - i));
+            break;
+        }
+        out.push_str(&line);
+    }
+    out
+}
+
+/// Denies the mute role send permissions in a channel. Returns false if the channel is of a kind
+/// where muting doesn't make sense.
+pub async fn deny_mute_role_in(ctx: &Context, channel: &GuildChannel, role: RoleId) -> crate::error::Result<bool> {
+    if !matches!(
+        channel.kind,
+        ChannelType::Text | ChannelType::Voice | ChannelType::Category | ChannelType::News
+    ) {
+        return Ok(false);
+    }
+
+    let overwrite = PermissionOverwrite {
+        allow: Permissions::empty(),
+        deny: MUTE_DENIED_PERMISSIONS,
+        kind: PermissionOverwriteType::Role(role),
+    };
+    channel.create_permission(ctx, &overwrite).await?;
+    Ok(true)
+}
+
+/// The outcome of [`setup_mute_role`].
+#[derive(Debug)]
+pub struct MuteRoleSetup {
+    /// The role now set as the `mute_role`.
+    pub role: VerifiedRole,
+    /// How many channels the role was denied send permissions in.
+    pub updated: usize,
+    /// Channels where the permission overwrite couldn't be applied.
+    pub failed: Vec<GuildChannel>,
+}
+
+/// Adopts or creates the mute role for a guild, sets it as the `mute_role` and denies it send permissions
+/// in every channel. A channel which can't be updated is logged and skipped rather than abandoning the
+/// rest, and is reported in the returned [`MuteRoleSetup`].
+pub async fn setup_mute_role(
+    dis: &Dispatch,
+    ctx: &Context,
+    gid: GuildId,
+    role: Option<&str>,
+) -> crate::error::Result<MuteRoleSetup> {
+    let db = dis.db(gid);
+    let mute_role_v = dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?;
+    let existing = mute_role_v.get(&db).await?;
+    let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+
+    let role = match (role, existing) {
+        (Some(r), _) => VerifiedRole::from_str_with_ctx(r, ctx, gid).await?,
+        (None, Some(r)) if guild.roles.contains_key(&r.into_inner()) => *r,
+        (None, _) => {
+            let created = gid
+                .create_role(ctx, |r| {
+                    r.name(DEFAULT_MUTE_ROLE_NAME)
+                        .permissions(Permissions::empty())
+                        .mentionable(false)
+                })
+                .await?;
+            VerifiedRole::from_known(created.id)
+        }
+    };
+    mute_role_v.set(&db, role).await?;
+
+    let mut updated = 0;
+    let mut failed = Vec::new();
+    for c in guild.channels.values() {
+        match deny_mute_role_in(ctx, c, role.into_inner()).await {
+            Ok(true) => updated += 1,
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Couldn't deny the mute role in {} ({}): {}", c.name, c.id, e);
+                failed.push(c.clone());
+            }
+        }
+    }
+
+    Ok(MuteRoleSetup { role, updated, failed })
+}
+
+/// Formats a list of channels for display, truncating it if it would be longer than `max_len`.
+pub fn format_channel_list(channels: &[GuildChannel], max_len: usize) -> String {
+    let mut out = String::new();
+    for (i, c) in channels.iter().enumerate() {
+        let line = format!("#{} ({})\n", c.name, c.id);
+        if out.len() + line.len() > max_len {
+            out.push_str(&format!("... and {} more", channels.len() - i));
+            break;
+        }
+        out.push_str(&line);
+    }
+    out
+}
+
 /// The kind of action to take against a user.
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -186,6 +547,8 @@ pub enum ActionKind {
     Ban,
     /// Applies the mute role to a user.
     Mute,
+    /// Deletes the offending message, i.e. one sent while spamming.
+    DeleteMessage,
 }
 
 impl ActionKind {
@@ -204,6 +567,7 @@ impl ActionKind {
             ActionKind::SoftBan => Color::FABLED_PINK,
             ActionKind::Ban => Self::TRAFFIC_RED,
             ActionKind::Mute => Color::DARK_BLUE,
+            ActionKind::DeleteMessage => Color::LIGHT_GREY,
         }
     }
 
@@ -215,6 +579,7 @@ impl ActionKind {
             ActionKind::SoftBan => "soft ban",
             ActionKind::Ban => "ban",
             ActionKind::Mute => "mute",
+            ActionKind::DeleteMessage => "message deletion",
         }
     }
 
@@ -226,6 +591,7 @@ impl ActionKind {
             ActionKind::SoftBan => "Soft ban",
             ActionKind::Ban => "Ban",
             ActionKind::Mute => "Mute",
+            ActionKind::DeleteMessage => "Message deletion",
         }
     }
 
@@ -336,20 +702,34 @@ impl ModAction {
             ActionKind::Mute => {
                 self.mute_user(dis, ctx).await?;
             }
+            ActionKind::DeleteMessage => {
+                let message = self.original_message.ok_or(NoMessageToDelete)?;
+                self.channel.delete_message(ctx, message).await?;
+            }
         }
 
+        // Schedule the reversal before anything else can fail, so a timed punishment never becomes permanent.
         if let Some(d) = self.duration() {
-            let chrono_dur = chrono::Duration::from_std(*d).unwrap_or_else(|_| (*ONE_HUNDREDISH_YEARS));
-            let a = match self.action {
-                ActionKind::Ban => Action::unban(self.user().user.id, self.guild(), chrono_dur),
-                ActionKind::Mute => Action::unmute(self.user().user.id, self.guild(), chrono_dur),
-                _ => {
-                    warn!("Got a duration with a nonsensical attribute.");
-                    return Ok(());
+            let chrono_dur = chrono::Duration::from_std(*d).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+            match self.action {
+                ActionKind::Ban => {
+                    Action::unban(self.user().user.id, self.guild(), chrono_dur)
+                        .store_action(dis)
+                        .await?
                 }
-            };
-            a.store_action(dis).await?;
+                ActionKind::Mute => {
+                    Action::unmute(self.user().user.id, self.guild(), chrono_dur)
+                        .store_action(dis)
+                        .await?
+                }
+                _ => warn!("Got a duration with a nonsensical attribute."),
+            }
         }
+
+        MemberRisk::new(dis.db(self.guild()))
+            .record_action(self.user().user.id, self.action)
+            .await
+            .log_error();
         Ok(())
     }
 
@@ -406,15 +786,50 @@ impl ModAction {
         }
     }
 
-    /// Mutes a user by adding the mute role to them.
+    /// Creates an embed summarizing this action being applied to many users for the mod log.
+    pub fn create_bulk_embed(&self, embed: &mut CreateEmbed, users: &[Member], failed: usize) {
+        let moderator = self.moderator.mention();
+        let reason = self.reason.clone().unwrap_or_else(|| "No reason specified.".into());
+        let mut user_list = format_member_list(users, MAX_USER_LIST_LEN);
+        if user_list.is_empty() {
+            user_list.push_str("None");
+        }
+
+        embed
+            .color(self.action.color())
+            .title(format!("Bulk {}", self.action.name()))
+            .field(format!("Users ({})", users.len()), user_list, false)
+            .field("Reason", reason, false)
+            .field("Moderator", moderator, false)
+            .field("Channel", self.channel.mention(), false);
+
+        if self.action.has_duration() {
+            let dur = self
+                .duration
+                .as_ref()
+                .map(|d| d.to_string().into())
+                .unwrap_or_else(|| Cow::from("Indefinite"));
+
+            embed.field("Duration", dur, false);
+        }
+
+        if failed > 0 {
+            embed.field("Failed", failed, false);
+        }
+    }
+
+    /// Mutes a user by adding the mute role to them. If the guild has no mute role yet, one is set up
+    /// first as if by `mod setup-mute`, so a mute never fails just for want of configuration.
     pub async fn mute_user(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
         let action = self;
         let cfg_db = DbContext::new(dis, action.guild());
-        let mute_role = dis
-            .config_value_t::<VerifiedRole>(MUTE_ROLE)?
-            .get(&cfg_db)
-            .await?
-            .ok_or(NoMuteRoleSet)?;
+        let mute_role = match dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?.get(&cfg_db).await? {
+            Some(r) => *r,
+            None => {
+                info!("No mute role set for {}, setting one up", action.guild());
+                setup_mute_role(dis, ctx, action.guild(), None).await?.role
+            }
+        };
         let mut mem = action.user().clone();
         mem.add_role(ctx, mute_role.into_inner()).await?;
         Ok(())
@@ -422,23 +837,54 @@ impl ModAction {
 
     /// Creates an embed and places it in the moderation log.
     pub async fn report_action(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
-        let action = self;
-        let mod_channel_v = dis.config_value_t::<VerifiedChannel>(MOD_CHANNEL)?;
-        let cfg_db = DbContext::new(dis, action.guild());
-        let mod_channel = mod_channel_v.get(&cfg_db).await?.ok_or(NoModChannelSet)?;
-        mod_channel
-            .into_inner()
-            .send_message(ctx, |e| {
-                e.embed(|emb| {
-                    action.create_embed(emb);
-                    emb
-                })
-            })
-            .await?;
-        Ok(())
+        send_to_mod_log(dis, ctx, self.guild(), |emb| {
+            self.create_embed(emb);
+            emb
+        })
+        .await
     }
 }
 
+/// Builds an embed with the given function and places it in the moderation log of a guild.
+pub async fn send_to_mod_log<F>(dis: &Dispatch, ctx: &Context, guild: GuildId, f: F) -> crate::error::Result<()>
+where
+    F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+{
+    let mod_channel = mod_log_channel(dis, guild).await?;
+    mod_channel.send_message(ctx, |e| e.embed(f)).await?;
+    Ok(())
+}
+
+/// Sends an embed with an attached file to the guild's mod log channel, i.e. for archives of deleted messages.
+pub async fn send_file_to_mod_log<F>(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+    file: AttachmentType<'_>,
+    f: F,
+) -> crate::error::Result<()>
+where
+    F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+{
+    let mod_channel = mod_log_channel(dis, guild).await?;
+    mod_channel.send_files(ctx, vec![file], |e| e.embed(f)).await?;
+    Ok(())
+}
+
+/// Looks up the configured mod log channel for a guild.
+async fn mod_log_channel(dis: &Dispatch, guild: GuildId) -> crate::error::Result<ChannelId> {
+    let mod_channel_v = dis.config_value_t::<VerifiedChannel>(MOD_CHANNEL)?;
+    let cfg_db = DbContext::new(dis, guild);
+    let mod_channel = mod_channel_v.get(&cfg_db).await?.ok_or(NoModChannelSet)?;
+    Ok(mod_channel.into_inner())
+}
+
+impl_err!(NoTargets, "No users matched the specified targets.", true);
+impl_err!(
+    NoMessageToDelete,
+    "A message deletion needs the message to delete.",
+    false
+);
 impl_err!(
     NoModChannelSet,
     "No mod channel has been set for this guild (`mod_log_channel`).",
@@ -446,6 +892,6 @@ impl_err!(
 );
 impl_err!(
     NoMuteRoleSet,
-    "No mute role has been set for this guild (`mute_role`).",
+    "No mute role has been set for this guild (`mute_role`). See `mod setup-mute`.",
     true
 );
@@ -38,10 +38,13 @@ impl Module for PrivilegeFilter {
             ModInfo::with_name("privilege-check", "")
                 .with_filter(true)
                 .with_sensitivity(Sensitivity::High)
-                .with_config_value(config::Value::<VerifiedRole>::new(
-                    PRIV_ROLE,
-                    "A role which may run commands requiring elevated privilege.",
-                ))
+                .with_config_value(
+                    config::Value::<VerifiedRole>::new(
+                        PRIV_ROLE,
+                        "A role which may run commands requiring elevated privilege.",
+                    )
+                    .with_example("Moderators"),
+                )
         });
         &INFO
     }
@@ -0,0 +1,290 @@
+//! Contains logic for scoring how risky a member is, based on how new they are and how they've behaved.
+//! The score is used to scrutinise the first messages of brand-new accounts more heavily.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use shrinkwraprs::Shrinkwrap;
+
+use crate::db::cache::TimedCache;
+pub use crate::db::storage::RiskRecord;
+use crate::db::DbContext;
+use crate::dispatch::Dispatch;
+use crate::module::moderation::ActionKind;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The number of messages after which a member no longer counts as new.
+pub const FIRST_MESSAGES: i32 = 20;
+/// Members who joined at least this long ago count as past their first messages even if Glimbot hasn't seen
+/// them, i.e. because they were already members when risk tracking was enabled.
+pub const ESTABLISHED_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How long risk records are cached for.
+pub const RISK_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Weight of account age in the risk score.
+const ACCOUNT_AGE_WEIGHT: f64 = 0.35;
+/// Weight of time since joining the guild in the risk score.
+const JOIN_AGE_WEIGHT: f64 = 0.2;
+/// Weight of having sent few messages in the risk score.
+const MESSAGES_WEIGHT: f64 = 0.15;
+/// Weight of past spam pressure in the risk score.
+const PRESSURE_WEIGHT: f64 = 0.15;
+/// Weight of past warnings and mutes in the risk score.
+const HISTORY_WEIGHT: f64 = 0.15;
+
+/// Cached risk records, keyed by guild and user.
+pub type RiskRecords = TimedCache<(GuildId, UserId), RiskRecord>;
+
+/// Wrapper around DbContext to store/retrieve member risk records.
+#[derive(Shrinkwrap)]
+pub struct MemberRisk<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> MemberRisk<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        MemberRisk {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves the risk record for a user, which is empty if Glimbot has never seen them.
+    pub async fn get(&self, user: UserId) -> crate::error::Result<RiskRecord> {
+        let r = self.ctx.storage().risk_record(self.ctx.guild(), user).await?;
+        Ok(r.unwrap_or_default())
+    }
+
+    /// Counts a message from a user, up to [`FIRST_MESSAGES`].
+    pub async fn record_message(&self, user: UserId) -> crate::error::Result<()> {
+        self.ctx
+            .storage()
+            .record_risk_message(self.ctx.guild(), user, FIRST_MESSAGES)
+            .await
+    }
+
+    /// Counts a warning or mute against a user. Other actions are ignored.
+    pub async fn record_action(&self, user: UserId, kind: ActionKind) -> crate::error::Result<()> {
+        let (warns, mutes) = match kind {
+            ActionKind::Warn => (1, 0),
+            ActionKind::Mute => (0, 1),
+            _ => return Ok(()),
+        };
+
+        self.ctx
+            .storage()
+            .record_risk_action(self.ctx.guild(), user, warns, mutes)
+            .await?;
+        self.ctx.dispatch().risk_records().remove(&(self.ctx.guild(), user));
+        Ok(())
+    }
+
+    /// Raises the peak pressure of users, if the given pressure is higher than what was stored.
+    pub async fn record_peak_pressures(&self, pressures: &[(UserId, f64)]) -> crate::error::Result<()> {
+        self.ctx
+            .storage()
+            .record_peak_pressures(self.ctx.guild(), pressures)
+            .await
+    }
+}
+
+/// Retrieves the risk record for a user, going to the database only if it isn't cached.
+pub async fn risk_record(dis: &Dispatch, guild: GuildId, user: UserId) -> crate::error::Result<RiskRecord> {
+    let f = async { MemberRisk::new(dis.db(guild)).get(user).await };
+    Ok(*dis.risk_records().get_or_insert_with(&(guild, user), f).await?)
+}
+
+/// Seeds a risk record from when the member joined, so members who joined at least [`ESTABLISHED_AFTER`] ago
+/// aren't scored as new just because Glimbot hasn't counted their messages.
+pub fn seed_from_join(record: RiskRecord, joined_at: Option<chrono::DateTime<Utc>>) -> RiskRecord {
+    if joined_at.map_or(false, |j| since(j) >= ESTABLISHED_AFTER) {
+        RiskRecord {
+            messages: record.messages.max(FIRST_MESSAGES),
+            ..record
+        }
+    } else {
+        record
+    }
+}
+
+/// A member's risk score, from 0 (established and well behaved) to 1 (brand new, or a known troublemaker),
+/// along with the signals it was built from.
+#[derive(Debug, Copy, Clone)]
+pub struct RiskScore {
+    /// How old the user's account is.
+    pub account_age: Duration,
+    /// How long ago the user joined the guild, if known.
+    pub join_age: Option<Duration>,
+    /// What Glimbot remembers about the member.
+    pub record: RiskRecord,
+    /// Risk from account age.
+    pub account: f64,
+    /// Risk from time since joining.
+    pub join: f64,
+    /// Risk from having sent few messages.
+    pub messages: f64,
+    /// Risk from past spam pressure.
+    pub pressure: f64,
+    /// Risk from past warnings and mutes.
+    pub history: f64,
+}
+
+/// Scores an age, where younger is riskier.
+fn age_risk(age: Duration, thresholds: [(Duration, f64); 3]) -> f64 {
+    thresholds.iter().find(|(t, _)| age < *t).map_or(0.0, |(_, r)| *r)
+}
+
+/// Converts a chrono duration since some time into a std duration, treating times in the future as now.
+pub fn since(t: chrono::DateTime<Utc>) -> Duration {
+    (Utc::now() - t).to_std().unwrap_or_default()
+}
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl RiskScore {
+    /// Scores a member. `max_pressure` is used to scale their peak spam pressure.
+    pub fn new(user: UserId, joined_at: Option<chrono::DateTime<Utc>>, record: RiskRecord, max_pressure: f64) -> Self {
+        let record = seed_from_join(record, joined_at);
+        let account_age = since(user.created_at());
+        let join_age = joined_at.map(since);
+
+        let account = age_risk(account_age, [(DAY, 1.0), (7 * DAY, 0.6), (30 * DAY, 0.3)]);
+        let join = join_age.map_or(0.0, |a| age_risk(a, [(HOUR, 1.0), (DAY, 0.6), (7 * DAY, 0.3)]));
+        let messages = 1.0 - (record.messages.clamp(0, FIRST_MESSAGES) as f64 / FIRST_MESSAGES as f64);
+        let pressure = if max_pressure > 0.0 {
+            (record.peak_pressure / max_pressure).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let history = ((record.warns + 2 * record.mutes) as f64 / 4.0).clamp(0.0, 1.0);
+
+        RiskScore {
+            account_age,
+            join_age,
+            record,
+            account,
+            join,
+            messages,
+            pressure,
+            history,
+        }
+    }
+
+    /// The weighted total, between 0 and 1.
+    pub fn total(&self) -> f64 {
+        self.account * ACCOUNT_AGE_WEIGHT
+            + self.join * JOIN_AGE_WEIGHT
+            + self.messages * MESSAGES_WEIGHT
+            + self.pressure * PRESSURE_WEIGHT
+            + self.history * HISTORY_WEIGHT
+    }
+}
+
+/// Rounds a duration to whole minutes for display.
+pub fn whole_minutes(d: Duration) -> Duration {
+    Duration::from_secs(d.as_secs() - d.as_secs() % 60)
+}
+
+impl fmt::Display for RiskScore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let join_age = self
+            .join_age
+            .map(|a| humantime::format_duration(whole_minutes(a)).to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        writeln!(f, "{:<10} {:>24} {:>6}", "signal", "value", "risk")?;
+        writeln!(
+            f,
+            "{:<10} {:>24} {:>6.2}",
+            "account",
+            humantime::format_duration(whole_minutes(self.account_age)).to_string(),
+            self.account
+        )?;
+        writeln!(f, "{:<10} {:>24} {:>6.2}", "joined", join_age, self.join)?;
+        writeln!(
+            f,
+            "{:<10} {:>24} {:>6.2}",
+            "messages",
+            format!("{}/{}", self.record.messages, FIRST_MESSAGES),
+            self.messages
+        )?;
+        writeln!(
+            f,
+            "{:<10} {:>24} {:>6.2}",
+            "pressure",
+            format!("{:.1} peak", self.record.peak_pressure),
+            self.pressure
+        )?;
+        writeln!(
+            f,
+            "{:<10} {:>24} {:>6.2}",
+            "history",
+            format!("{} warns, {} mutes", self.record.warns, self.record.mutes),
+            self.history
+        )?;
+        write!(f, "{:<10} {:>24} {:>6.2}", "total", "", self.total())
+    }
+}
+
+/// Scores a member of a guild.
+pub async fn risk_score(
+    dis: &Dispatch,
+    guild: GuildId,
+    user: UserId,
+    joined_at: Option<chrono::DateTime<Utc>>,
+    max_pressure: f64,
+) -> crate::error::Result<RiskScore> {
+    let record = risk_record(dis, guild, user).await?;
+    Ok(RiskScore::new(user, joined_at, record, max_pressure))
+}
+
+/// Counts the first messages members send, for risk scoring.
+pub struct RiskModule;
+
+#[async_trait::async_trait]
+impl Module for RiskModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("risk", "tracks new members' first messages for risk scoring.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_message_hook(true)
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, _ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let guild = match orig.guild_id {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+        if orig.author.bot {
+            return Ok(());
+        }
+
+        let joined_at = orig.member.as_ref().and_then(|m| m.joined_at);
+        let record = seed_from_join(risk_record(dis, guild, orig.author.id).await?, joined_at);
+        if record.messages >= FIRST_MESSAGES {
+            return Ok(());
+        }
+
+        MemberRisk::new(dis.db(guild)).record_message(orig.author.id).await?;
+        dis.risk_records().insert(
+            &(guild, orig.author.id),
+            RiskRecord {
+                messages: record.messages + 1,
+                ..record
+            },
+        );
+        Ok(())
+    }
+}
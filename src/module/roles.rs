@@ -6,16 +6,20 @@ use futures::StreamExt;
 use once_cell::sync::Lazy;
 use serenity::client::Context;
 use serenity::model::channel::Message;
-use serenity::model::prelude::RoleId;
+use serenity::model::misc::Mentionable;
+use serenity::model::prelude::{RoleId, UserId};
 use serenity::utils::MessageBuilder;
 use shrinkwraprs::Shrinkwrap;
 use structopt::StructOpt;
 
+pub use crate::db::storage::{AlreadyJoinable, TooManyRoles};
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_HUNDREDISH_YEARS};
 use crate::db::DbContext;
 use crate::dispatch::config::VerifiedRole;
 use crate::dispatch::config::{FromStrWithCtx, NoSuchUser, RoleExt, VerifiedUser};
 use crate::dispatch::Dispatch;
-use crate::error::{DatabaseError, GuildNotInCache, RoleNotInCache};
+use crate::error::{GuildNotInCache, LogErrorExt, RoleNotInCache};
+use crate::module::moderation::send_to_mod_log;
 use crate::module::privilege::ensure_authorized_for_role;
 use crate::module::{ModInfo, Module, Sensitivity};
 use crate::util::ClapExt;
@@ -48,13 +52,6 @@ pub struct JoinableRoles<'pool> {
     ctx: DbContext<'pool>,
 }
 
-impl_err!(
-    TooManyRoles,
-    "Can't add more roles to joinable; you have too many!",
-    true
-);
-impl_err!(AlreadyJoinable, "This role is already joinable.", true);
-
 impl<'pool> JoinableRoles<'pool> {
     /// Creates a wrapper around the database context.
     pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
@@ -66,65 +63,32 @@ impl<'pool> JoinableRoles<'pool> {
     /// Inserts a new joinable role into the database.
     /// This will error if the guild has too many roles or if the role is already joinable.
     pub async fn add_joinable_role(&self, role: VerifiedRole) -> crate::error::Result<()> {
-        let res: Result<_, sqlx::Error> = sqlx::query!(
-            "INSERT INTO joinable_roles (guild, role) VALUES ($1, $2);",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .execute(self.ctx.conn())
-        .await;
-
-        if let Err(e) = res {
-            if e.is_check() {
-                Err(TooManyRoles.into())
-            } else if e.is_unique() {
-                Err(AlreadyJoinable.into())
-            } else {
-                Err(e.into())
-            }
-        } else {
-            Ok(())
-        }
+        self.ctx
+            .storage()
+            .add_joinable_role(self.ctx.guild(), role.into_inner())
+            .await
     }
 
     /// Removes a role from the joinable list.
     pub async fn del_joinable_role(&self, role: VerifiedRole) -> crate::error::Result<()> {
-        sqlx::query!(
-            "DELETE FROM joinable_roles WHERE guild = $1 AND role = $2;",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .execute(self.ctx.conn())
-        .await?;
-
-        Ok(())
+        self.ctx
+            .storage()
+            .del_joinable_role(self.ctx.guild(), role.into_inner())
+            .await
     }
 
     /// Returns whether or not the role is in the joinable roles list.
     pub async fn is_joinable(&self, role: VerifiedRole) -> crate::error::Result<bool> {
-        Ok(sqlx::query_scalar!(
-            "SELECT COUNT(*) AS matching FROM joinable_roles WHERE guild = $1 AND role = $2;",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .fetch_one(self.ctx.conn())
-        .await?
-        .unwrap_or_default()
-            > 0)
+        self.ctx
+            .storage()
+            .is_joinable(self.ctx.guild(), role.into_inner())
+            .await
     }
 
     /// Retrieves the list of joinable roles. Keeping this query sane is why
     /// we limit the number of joinable roles.
     pub async fn joinable_roles(&self) -> crate::error::Result<Vec<RoleId>> {
-        let s: Vec<i64> = sqlx::query_scalar!(
-            "SELECT role FROM joinable_roles WHERE guild = $1 ORDER BY role ASC;",
-            self.ctx.guild_as_i64()
-        )
-        .fetch_all(self.ctx.conn())
-        .await?;
-        let mut out = Vec::with_capacity(s.len());
-        out.extend(s.into_iter().map(|r| RoleId::from(r as u64)));
-        Ok(out)
+        self.ctx.storage().joinable_roles(self.ctx.guild()).await
     }
 }
 
@@ -154,7 +118,7 @@ impl Module for RoleModule {
         let gid = orig.guild_id.unwrap();
 
         let db = DbContext::new(dis, gid);
-        let join = JoinableRoles::new(db);
+        let join = JoinableRoles::new(&db);
 
         match &role_opts {
             RoleOpt::Join { .. } | RoleOpt::Leave { .. } => {
@@ -192,7 +156,7 @@ impl Module for RoleModule {
             }
             RoleOpt::ListJoinable => {
                 let roles = join.joinable_roles().await?;
-                let roles: Vec<_> = futures::stream::iter(roles.into_iter())
+                let roles: Vec<_> = futures::stream::iter(roles)
                     .then(|r| async move { r.to_role_name_or_id(ctx, gid).await })
                     .collect()
                     .await;
@@ -247,6 +211,10 @@ enum ModRoleOpt {
         role: String,
         /// The user to assign/unassign a role to.
         user: String,
+        /// How long the user should keep the role. Specified in human format, i.e. "5d 2h 5m"
+        /// Max 100 years, min 1 minute. If unspecified, the role is kept until it is unassigned.
+        #[structopt(short = "d")]
+        duration: Option<humantime::Duration>,
     },
     /// Unassign a role to a user.
     Unassign {
@@ -281,6 +249,14 @@ impl ModRoleOpt {
     pub fn is_assign(&self) -> bool {
         matches!(self, ModRoleOpt::Assign { .. })
     }
+
+    /// Extracts how long an assigned role should be kept, if specified.
+    pub fn duration(&self) -> Option<humantime::Duration> {
+        match self {
+            ModRoleOpt::Assign { duration, .. } => *duration,
+            _ => None,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -316,7 +292,7 @@ impl Module for ModRoleModule {
         ensure_authorized_for_role(ctx, &auth_mem, &full_role).await?;
 
         let db = DbContext::new(dis, gid);
-        let join = JoinableRoles::new(db);
+        let join = JoinableRoles::new(&db);
         let user = futures::stream::iter(opts.extract_user())
             .then(|s| VerifiedUser::from_str_with_ctx(s, ctx, gid))
             .next()
@@ -344,9 +320,22 @@ impl Module for ModRoleModule {
 
                 if opts.is_assign() {
                     member.add_role(ctx, role.into_inner()).await?;
+                    // Whatever was assigned last wins, so a permanent assignment isn't undone by an earlier
+                    // temporary one expiring.
+                    cancel_role_removals(&db, user.into_inner(), role.into_inner()).await?;
+                    if let Some(d) = opts.duration() {
+                        let chrono_dur = chrono::Duration::from_std(*d).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+                        Action::remove_role(user.into_inner(), gid, role.into_inner(), chrono_dur)
+                            .store_action(dis)
+                            .await?;
+                        report_temporary_role(dis, ctx, orig, user, &full_role.name, d)
+                            .await
+                            .log_error();
+                    }
                     "Added role to user."
                 } else {
                     member.remove_role(ctx, role.into_inner()).await?;
+                    cancel_role_removals(&db, user.into_inner(), role.into_inner()).await?;
                     "Removed role from user if they had it."
                 }
             }
@@ -356,3 +345,33 @@ impl Module for ModRoleModule {
         Ok(())
     }
 }
+
+/// Drops any pending timed removals of a role from a user.
+pub async fn cancel_role_removals(db: &DbContext<'_>, user: UserId, role: RoleId) -> crate::error::Result<()> {
+    let events = TimedEvents::new(db.clone());
+    for a in events.actions_for_user(user).await? {
+        if a.kind() == ActionKind::RemoveRole(role) {
+            events.drop_action(&a).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Notes in the mod log that a role was assigned to a user for a limited time.
+async fn report_temporary_role(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    user: VerifiedUser,
+    role_name: &str,
+    duration: humantime::Duration,
+) -> crate::error::Result<()> {
+    send_to_mod_log(dis, ctx, orig.guild_id.unwrap(), |emb| {
+        emb.title("Temporary role")
+            .field("User", user.into_inner().mention(), false)
+            .field("Role", role_name, false)
+            .field("Moderator", orig.author.id.mention(), false)
+            .field("Duration", duration, false)
+    })
+    .await
+}
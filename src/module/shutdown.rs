@@ -5,6 +5,7 @@ use serenity::client::Context;
 use serenity::model::channel::Message;
 
 use crate::dispatch::{Dispatch, ShardManKey};
+use crate::module::confirm::confirm_if_required;
 use crate::module::{ModInfo, Module, Sensitivity};
 
 /// Owner-only command to shutdown Glimbot by terminating the shards.
@@ -24,12 +25,17 @@ impl Module for Shutdown {
 
     async fn process(
         &self,
-        _dis: &Dispatch,
+        dis: &Dispatch,
         ctx: &Context,
         orig: &Message,
         _command: Vec<String>,
     ) -> crate::error::Result<()> {
         info!("received shutdown command");
+        if !confirm_if_required(dis, ctx, orig, "shutdown", "Shut down Glimbot in every guild.").await? {
+            info!("shutdown cancelled");
+            return Ok(());
+        }
+
         let man = {
             ctx.data
                 .read()
@@ -0,0 +1,290 @@
+//! Contains logic for automatically raising and lowering slowmode in busy channels.
+//! Message rates are calculated from [`Dispatch::message_cache`], so only messages Glimbot has seen count.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{Channel, Message};
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::misc::Mentionable;
+
+use crate::db::cache::Cache;
+use crate::dispatch::config::Value;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::send_to_mod_log;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::clock::CacheInstant;
+use crate::util::constraints::FieldConstraint;
+
+/// Config key for the channels which should have automatic slowmode.
+pub const SLOWMODE_CHANNELS: &str = "slowmode_channels";
+/// The window over which message rates are measured. Slowmode is changed at most once per window per channel.
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// The smallest non-zero slowmode Glimbot will set; slowmode doubles from here as a channel gets busier.
+pub const SLOWMODE_STEP: Duration = Duration::from_secs(5);
+/// The longest slowmode Discord allows.
+pub const MAX_SLOWMODE: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The automatic slowmode bounds for a single channel.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct ChannelSlowmode {
+    /// Messages per minute above which slowmode is raised.
+    pub threshold: u32,
+    /// Messages per minute below which slowmode is lowered again. Defaults to half of `threshold`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<u32>,
+    /// The slowmode the channel returns to once it's quiet.
+    #[serde(default, with = "humantime_serde")]
+    pub min_delay: Duration,
+    /// The most slowmode Glimbot will apply.
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+}
+
+impl ChannelSlowmode {
+    /// The rate at which slowmode is lowered again.
+    pub fn cooldown(&self) -> u32 {
+        self.cooldown.unwrap_or(self.threshold / 2)
+    }
+
+    /// The slowmode to use when the channel is too busy.
+    pub fn raised(&self, current: Duration) -> Duration {
+        (current * 2)
+            .max(SLOWMODE_STEP)
+            .max(self.min_delay)
+            .min(self.max_delay)
+            .min(MAX_SLOWMODE)
+    }
+
+    /// The slowmode to use once the channel has cooled down.
+    pub fn lowered(&self, current: Duration) -> Duration {
+        let half = current / 2;
+        if half < SLOWMODE_STEP {
+            self.min_delay
+        } else {
+            half.max(self.min_delay)
+        }
+    }
+}
+
+/// Per-channel automatic slowmode settings for a guild.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SlowmodeChannels(BTreeMap<ChannelId, ChannelSlowmode>);
+
+impl SlowmodeChannels {
+    /// Retrieves the settings for a channel, if it has automatic slowmode.
+    pub fn get(&self, channel: ChannelId) -> Option<&ChannelSlowmode> {
+        self.0.get(&channel)
+    }
+}
+
+impl FromStr for SlowmodeChannels {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let out: SlowmodeChannels = serde_json::from_str(s)?;
+        if out.0.values().any(|c| c.min_delay > c.max_delay) {
+            return Err(serde::de::Error::custom("min_delay must not be greater than max_delay"));
+        }
+        // Otherwise slowmode would be lowered as soon as it was raised, and raised again on the next message.
+        if out.0.values().any(|c| c.cooldown() >= c.threshold) {
+            return Err(serde::de::Error::custom("cooldown must be less than threshold"));
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Display for SlowmodeChannels {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Raises slowmode in channels with a high message rate, and lowers it again once they cool down.
+#[derive(Default)]
+pub struct SlowmodeModule {
+    /// Channels Glimbot has raised slowmode in. Only these are ever lowered, so manual slowmode is left alone.
+    raised: Cache<ChannelId, GuildId>,
+    /// When slowmode was last changed in a channel.
+    last_change: Cache<ChannelId, CacheInstant>,
+}
+
+/// Counts the messages sent in a channel during the last [`RATE_WINDOW`].
+pub fn message_rate(dis: &Dispatch, guild: GuildId, channel: ChannelId) -> usize {
+    let cutoff = Utc::now() - chrono::Duration::from_std(RATE_WINDOW).unwrap();
+    dis.message_cache()
+        .get(&guild)
+        .map(|cv| {
+            cv.snapshot()
+                .iter()
+                .rev()
+                .take_while(|m| m.timestamp > cutoff)
+                .filter(|m| m.channel == channel)
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// Retrieves the current slowmode of a channel from the cache.
+async fn current_slowmode(ctx: &Context, channel: ChannelId) -> Duration {
+    match channel.to_channel_cached(ctx).await {
+        Some(Channel::Guild(gc)) => Duration::from_secs(gc.slow_mode_rate.unwrap_or(0)),
+        _ => Duration::from_secs(0),
+    }
+}
+
+impl SlowmodeModule {
+    /// Whether enough time has passed since the last change to change slowmode in a channel again.
+    fn can_change(&self, channel: ChannelId) -> bool {
+        self.last_change
+            .get(&channel)
+            .map_or(true, |t| t.elapsed() >= RATE_WINDOW)
+    }
+
+    /// Lowers slowmode in a channel Glimbot raised it in, if the channel has cooled down.
+    async fn lower_if_cool(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        channel: ChannelId,
+    ) -> crate::error::Result<()> {
+        if !self.can_change(channel) {
+            return Ok(());
+        }
+
+        let conf = dis
+            .config_value_t::<SlowmodeChannels>(SLOWMODE_CHANNELS)?
+            .get_or_default(&dis.db(guild))
+            .await?;
+        let conf = match conf.get(channel) {
+            Some(c) => *c,
+            None => {
+                // Automatic slowmode was turned off for the channel; leave it as it is.
+                self.raised.remove(&channel);
+                return Ok(());
+            }
+        };
+
+        let rate = message_rate(dis, guild, channel);
+        if rate > conf.cooldown() as usize {
+            return Ok(());
+        }
+
+        let current = current_slowmode(ctx, channel).await;
+        let lowered = conf.lowered(current);
+        if lowered <= conf.min_delay {
+            self.raised.remove(&channel);
+        }
+        if lowered != current {
+            self.set_slowmode(dis, ctx, guild, channel, current, lowered, rate)
+                .await
+                .log_error();
+        }
+        Ok(())
+    }
+
+    /// Sets slowmode in a channel and reports the change to the mod log.
+    #[allow(clippy::too_many_arguments)]
+    async fn set_slowmode(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        channel: ChannelId,
+        from: Duration,
+        to: Duration,
+        rate: usize,
+    ) -> crate::error::Result<()> {
+        channel.edit(ctx, |c| c.slow_mode_rate(to.as_secs())).await?;
+        self.last_change.insert(&channel, CacheInstant::now());
+
+        let verb = if to > from { "raised" } else { "lowered" };
+        debug!("slowmode {} to {:?} at {} messages/min", verb, to, rate);
+        send_to_mod_log(dis, ctx, guild, |e| {
+            e.title("Slowmode Changed")
+                .description(format!(
+                    "Slowmode {} in {} from {} to {}.",
+                    verb,
+                    channel.mention(),
+                    humantime::format_duration(from),
+                    humantime::format_duration(to)
+                ))
+                .field("Messages/min", rate, true)
+                .timestamp(&Utc::now())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for SlowmodeModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("slowmode", "automatically raises slowmode in busy channels and lowers it afterwards.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_message_hook(true)
+                .with_tick_hook(true)
+                .with_config_value(Value::<SlowmodeChannels>::with_default(
+                    SLOWMODE_CHANNELS,
+                    "A JSON object mapping channel IDs to automatic slowmode settings. See Glimbot's documentation for more info.",
+                    Default::default,
+                )
+                .with_constraint(FieldConstraint::at_least("*.threshold", 1.0))
+                .with_example(r#"{"123456789012345678": {"threshold": 30, "max_delay": "1m"}}"#))
+        });
+        &INFO
+    }
+
+    async fn on_tick(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        for (channel, guild) in self.raised.entries() {
+            // One guild's failure to load its config shouldn't keep slowmode raised everywhere else.
+            self.lower_if_cool(dis, ctx, *guild, channel).await.log_error();
+        }
+
+        Ok(())
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let guild = match orig.guild_id {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+
+        let conf = dis
+            .config_value_t::<SlowmodeChannels>(SLOWMODE_CHANNELS)?
+            .get_or_default(&dis.db(guild))
+            .await?;
+        let conf = match conf.get(orig.channel_id) {
+            Some(c) => *c,
+            None => return Ok(()),
+        };
+
+        let rate = message_rate(dis, guild, orig.channel_id);
+        if rate <= conf.threshold as usize || !self.can_change(orig.channel_id) {
+            return Ok(());
+        }
+
+        let current = current_slowmode(ctx, orig.channel_id).await;
+        let raised = conf.raised(current);
+        if raised <= current {
+            return Ok(());
+        }
+
+        // Failing to adjust slowmode shouldn't stop the other hooks or the message's command.
+        self.raised.insert(&orig.channel_id, guild);
+        self.set_slowmode(dis, ctx, guild, orig.channel_id, current, raised, rate)
+            .await
+            .log_error();
+        Ok(())
+    }
+}
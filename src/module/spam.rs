@@ -9,29 +9,39 @@ use serenity::model::channel::Message;
 
 use std::{fmt, time};
 
-use crate::db::cache::{Cache, TimedCache};
+use crate::db::cache::{Cache, Cached, LruEvictionStrategy};
+use crate::db::storage::StoredPressure;
+use crate::db::DbContext;
 use crate::dispatch::config;
 use crate::dispatch::message_info::MsgInfo;
 use crate::dispatch::Dispatch;
 use crate::error::{GuildNotInCache, LogErrorExt};
-use crate::module::moderation::{ActionKind, ModAction};
+use crate::module::confirm::confirm_if_required;
+use crate::module::moderation::{send_file_to_mod_log, send_to_mod_log, ActionKind, ModAction};
 use crate::module::privilege::PRIV_ROLE;
+use crate::module::risk::{risk_score, MemberRisk};
 use crate::util::clock::CacheInstant;
-use crate::util::constraints::ConstrainedU64;
-use crate::util::ClapExt;
+use crate::util::constraints::{ConstrainedU64, FieldConstraint};
+use crate::util::{ClapExt, MessageRef};
 
 use chrono::Utc;
 
-use futures::StreamExt;
 use num::{ToPrimitive, Zero};
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use regex::Regex;
-use serenity::model::id::{GuildId, UserId};
+use serenity::http::AttachmentType;
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use serenity::model::misc::Mentionable;
 
 use serenity::model::prelude::ReactionType::Unicode;
+use serenity::utils::MessageBuilder;
 
+use shrinkwraprs::Shrinkwrap;
+use std::borrow::Borrow;
 use std::fmt::Formatter;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Base pressure generated by sending a message.
@@ -46,23 +56,67 @@ pub const DEFAULT_LENGTH_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PR
 pub const DEFAULT_LINE_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 70.0;
 /// Default pressure per ping.
 pub const DEFAULT_PING_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 20.0;
+/// Default pressure for attachments which aren't images.
+pub const DEFAULT_FILE_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 6.0;
+/// Default pressure per MiB of attachments.
+pub const DEFAULT_ATTACHMENT_SIZE_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 100.0;
+/// Default pressure per sticker.
+pub const DEFAULT_STICKER_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 6.0;
+/// Default pressure per custom emoji.
+pub const DEFAULT_EMOJI_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 20.0;
+/// Default pressure per link embed.
+pub const DEFAULT_EMBED_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 8.0;
 /// Default pressure decay; this is the period in seconds for removal of one base pressure.
 pub const DEFAULT_PRESSURE_DECAY: f64 = 2.5;
 /// Default silence timeout; this the duration of any automutes Glimbot performs.
 pub const DEFAULT_SILENCE_TIMEOUT: time::Duration = time::Duration::from_secs(10 * 60);
 
+/// The most messages a single `spam clean` invocation may delete.
+pub const MAX_CLEAN: u64 = 5000;
+/// The most messages `spam clean` will look through while searching for matches.
+pub const MAX_CLEAN_SCAN: usize = 25_000;
+/// The number of messages fetched per history request; also the bulk deletion limit.
+pub const HISTORY_PAGE_SIZE: u64 = 100;
+/// Discord refuses to bulk delete messages older than this many days.
+pub const BULK_DELETE_MAX_AGE_DAYS: i64 = 14;
+/// How many recent messages in each channel the Discord cache keeps in full, so `spam clean` can usually
+/// find what it's deleting without asking the API.
+pub const CACHED_MESSAGES_PER_CHANNEL: usize = 500;
+/// The largest archive file `spam clean` posts to the mod log; bigger archives are split across several posts,
+/// to stay under Discord's upload limit.
+pub const MAX_ARCHIVE_BYTES: usize = 7 * 1024 * 1024;
+
+/// The most users `spam pressure top` will list.
+pub const MAX_PRESSURE_TOP: u64 = 50;
+/// How often user pressure is written to the database, in addition to on shutdown.
+pub const PRESSURE_PERSIST_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// The most users to track pressure for in each guild. The least recently active users are dropped first.
+pub const PRESSURE_CACHE_CAPACITY: usize = 16_384;
+/// The most users to remember recent spam mutes for in each guild. The least recently muted users are dropped first.
+pub const MUTE_HISTORY_CAPACITY: usize = 1024;
+
 /// The config key for grabbing a [`SpamConfig`].
 pub const SPAM_CONFIG_KEY: &str = "spam_config";
 /// The config key for grabbing a role that should be immune to spam checks.
 /// Guild owners and moderators cannot generate pressure.
 pub const SPAM_IGNORE_ROLE: &str = "spam_ignore_role";
 
+/// The config key for shadow mode, where the live spam config only logs what it would have done.
+pub const SPAM_SHADOW_MODE: &str = "spam_shadow_mode";
+/// The config key for a candidate [`SpamConfig`] which is always run in shadow mode next to the live one.
+pub const SPAM_SHADOW_CONFIG: &str = "spam_shadow_config";
+
 /// Matches known vertical whitespace characters; we count each of them as a "line" separator.
 pub static VERTICAL_WHITESPACE_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"[\r\v\f\n\u2028\u2029]"#).expect("Invalid vertical whitespace RE"));
+/// Matches custom emoji, animated or not.
+pub static CUSTOM_EMOJI_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<a?:\w+:\d+>"#).expect("Invalid custom emoji RE"));
+/// Matches links Discord would embed; links wrapped in angle brackets don't embed.
+pub static EMBED_LINK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?:^|[^<])https?://[^\s>]+"#).expect("Invalid embed link RE"));
 
 /// The numerical configuration values for the spam module.
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SpamConfig {
     /// Base pressure generated by sending a message.
     pub base_pressure: R64,
@@ -78,11 +132,206 @@ pub struct SpamConfig {
     pub ping_pressure: R64,
     /// The amount of time it will take for one `base_pressure` worth of pressure to decay.
     pub pressure_decay: R64,
+    /// Pressure generated by each attachment which isn't an image.
+    #[serde(default = "default_file_pressure")]
+    pub file_pressure: R64,
+    /// Pressure generated per MiB of attachments.
+    #[serde(default = "default_attachment_size_pressure")]
+    pub attachment_size_pressure: R64,
+    /// Pressure generated by each sticker in a message.
+    #[serde(default = "default_sticker_pressure")]
+    pub sticker_pressure: R64,
+    /// Pressure generated by each custom emoji in a message.
+    #[serde(default = "default_emoji_pressure")]
+    pub emoji_pressure: R64,
+    /// Pressure generated by each link embed in a message.
+    #[serde(default = "default_embed_pressure")]
+    pub embed_pressure: R64,
     /// The amount of time users will be muted for.
     #[serde(with = "humantime_serde")]
     pub silence_timeout: time::Duration,
+    /// Escalating responses to spam. If empty, users are muted for `silence_timeout` once they pass `max_pressure`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub responses: Vec<SpamResponse>,
+    /// What to do with users who keep getting muted for spam.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_mutes: Option<RepeatMutes>,
+    /// Pressure multipliers for members with certain roles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub role_multipliers: Vec<RoleMultiplier>,
+    /// How much a member's risk score scales their pressure; pressure is multiplied by `1 + risk * risk_multiplier`.
+    #[serde(default)]
+    pub risk_multiplier: R64,
+}
+
+/// Scales the pressure generated by members with a role.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct RoleMultiplier {
+    /// The role to apply the multiplier to.
+    pub role: RoleId,
+    /// The multiplier; 0 means members with the role never generate pressure.
+    pub multiplier: R64,
+}
+
+#[doc(hidden)]
+fn default_file_pressure() -> R64 {
+    R64::new(DEFAULT_FILE_PRESSURE)
+}
+
+#[doc(hidden)]
+fn default_attachment_size_pressure() -> R64 {
+    R64::new(DEFAULT_ATTACHMENT_SIZE_PRESSURE)
+}
+
+#[doc(hidden)]
+fn default_sticker_pressure() -> R64 {
+    R64::new(DEFAULT_STICKER_PRESSURE)
+}
+
+#[doc(hidden)]
+fn default_emoji_pressure() -> R64 {
+    R64::new(DEFAULT_EMOJI_PRESSURE)
+}
+
+#[doc(hidden)]
+fn default_embed_pressure() -> R64 {
+    R64::new(DEFAULT_EMBED_PRESSURE)
+}
+
+/// An automatic response to spam.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpamAction {
+    /// Reacts to the message, DMs the user a warning, and logs a warning in the mod log.
+    Warn,
+    /// Deletes the message. Unlike the other actions, this is repeated for every message sent while
+    /// the user's pressure stays in range.
+    Delete,
+    /// Mutes the user.
+    Mute,
+    /// Kicks the user.
+    Kick,
+    /// Bans the user.
+    Ban,
+}
+
+impl SpamAction {
+    /// The moderation action this corresponds to.
+    pub fn action_kind(&self) -> ActionKind {
+        match self {
+            SpamAction::Warn => ActionKind::Warn,
+            SpamAction::Delete => ActionKind::DeleteMessage,
+            SpamAction::Mute => ActionKind::Mute,
+            SpamAction::Kick => ActionKind::Kick,
+            SpamAction::Ban => ActionKind::Ban,
+        }
+    }
+
+    /// The lower-case name of the action, for messages like "would have muted".
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            SpamAction::Warn => "warned",
+            SpamAction::Delete => "deleted a message from",
+            SpamAction::Mute => "muted",
+            SpamAction::Kick => "kicked",
+            SpamAction::Ban => "banned",
+        }
+    }
+}
+
+/// One rung of the spam response ladder.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct SpamResponse {
+    /// The pressure a user must pass for this response to apply.
+    pub pressure: R64,
+    /// What to do.
+    pub action: SpamAction,
+    /// How long a mute or ban should last. Mutes and bans without a duration are indefinite.
+    #[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<time::Duration>,
+    /// The risk score, between 0 and 1, a member must have for this response to apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_risk: Option<R64>,
+}
+
+/// Escalation for users who are repeatedly muted for spam within a window of time.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct RepeatMutes {
+    /// The number of mutes, including the current one, which trigger the escalation.
+    pub count: u32,
+    /// The window in which mutes are counted.
+    #[serde(with = "humantime_serde")]
+    pub window: time::Duration,
+    /// What to do instead of muting; either `kick` or `ban`.
+    pub action: SpamAction,
+    /// How long a ban should last.
+    #[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<time::Duration>,
+}
+
+impl SpamConfig {
+    /// The response ladder ordered by pressure, falling back to a single mute at `max_pressure`.
+    pub fn ladder(&self) -> Vec<SpamResponse> {
+        if self.responses.is_empty() {
+            let duration = Some(self.silence_timeout).filter(|d| *d > Duration::from_secs(0));
+            return vec![SpamResponse {
+                pressure: self.max_pressure,
+                action: SpamAction::Mute,
+                duration,
+                min_risk: None,
+            }];
+        }
+
+        let mut ladder = self.responses.clone();
+        ladder.sort_by_key(|r| r.pressure);
+        ladder
+    }
+
+    /// The index of the highest rung of the ladder a user with the given pressure has reached.
+    pub fn rung_for(&self, pressure: R64) -> Option<usize> {
+        self.ladder().iter().rposition(|r| pressure > r.pressure)
+    }
+
+    /// The pressure multiplier for a member with the given roles. If a member has several roles with
+    /// multipliers, the one highest in the guild's role hierarchy wins.
+    pub async fn role_multiplier(&self, ctx: &Context, guild: GuildId, roles: &[RoleId]) -> R64 {
+        let mut best: Option<(i64, R64)> = None;
+        for rm in self.role_multipliers.iter().filter(|rm| roles.contains(&rm.role)) {
+            let position = match ctx.cache.role(guild, rm.role).await {
+                Some(r) => r.position,
+                None => continue,
+            };
+            if best.map_or(true, |(p, _)| position > p) {
+                best = Some((position, rm.multiplier));
+            }
+        }
+        best.map_or_else(|| R64::new(1.0), |(_, m)| m)
+    }
+
+    /// Whether pressure depends on members' risk scores at all.
+    pub fn uses_risk(&self) -> bool {
+        self.risk_multiplier > 0.0 || self.responses.iter().any(|r| r.min_risk.is_some())
+    }
+
+    /// Picks the response for a user with the given risk score who moved from rung `prev` to rung `now`.
+    /// Responses are only applied when a user climbs the ladder, except for deletion, and are skipped if the
+    /// user's risk is below the rung's `min_risk`.
+    pub fn response_for(&self, prev: Option<usize>, now: Option<usize>, risk: R64) -> Option<SpamResponse> {
+        let now = now?;
+        let resp = self.ladder()[now];
+        if resp.min_risk.map_or(false, |m| risk < m) {
+            return None;
+        }
+        let climbed = prev.map_or(true, |p| now > p);
+        if climbed || resp.action == SpamAction::Delete {
+            Some(resp)
+        } else {
+            None
+        }
+    }
 }
 
+/// Only parses the config; its fields are checked against [`SPAM_CONFIG_CONSTRAINTS`] when it's set.
 impl FromStr for SpamConfig {
     type Err = serde_json::Error;
 
@@ -102,6 +351,9 @@ impl fmt::Display for SpamConfig {
 pub struct UserPressure {
     last_update: CacheInstant,
     pressure: R64,
+    /// The highest rung of the spam response ladder applied to the user since their pressure was last below it.
+    #[serde(default)]
+    rung: Option<usize>,
 }
 
 impl Default for UserPressure {
@@ -109,6 +361,7 @@ impl Default for UserPressure {
         Self {
             last_update: CacheInstant::now(),
             pressure: R64::zero(),
+            rung: None,
         }
     }
 }
@@ -116,26 +369,101 @@ impl Default for UserPressure {
 impl UserPressure {
     pub fn update(mut self, new_pressure: R64, conf: &SpamConfig) -> UserPressure {
         // First apply the decay.
-        if conf.pressure_decay != 0.0 && self.pressure != 0.0 {
-            let elapsed = self.last_update.elapsed();
-            let decay = R64::try_new(elapsed.as_secs_f64()).unwrap_or_else(R64::zero).raw()
-                / conf.pressure_decay.raw().clamp(0.0, f64::MAX);
-            let decay = decay * conf.base_pressure.raw();
-            let new_pressure = (self.pressure.raw() - decay).clamp(0.0, f64::MAX);
-            self.pressure = R64::new(new_pressure);
-        }
-
+        self.pressure = decayed_pressure(self.pressure, self.last_update.elapsed(), conf);
         self.pressure = R64::new((self.pressure.raw() + new_pressure.raw()).clamp(0.0, f64::MAX));
         self.last_update = CacheInstant::now();
+        self.rung = conf.rung_for(self.pressure);
         self
     }
 
+    /// The rung of the response ladder the user was last on, if their pressure hasn't since decayed below it.
+    pub fn current_rung(&self, conf: &SpamConfig) -> Option<usize> {
+        std::cmp::min(self.rung, conf.rung_for(self.current_pressure(conf)))
+    }
+
     pub fn with_pressure(new_pressure: R64) -> UserPressure {
         Self {
             pressure: new_pressure,
             ..Default::default()
         }
     }
+
+    /// The pressure as of the last update, without any decay applied.
+    pub fn pressure(&self) -> R64 {
+        self.pressure
+    }
+
+    /// The pressure right now, with decay applied.
+    pub fn current_pressure(&self, conf: &SpamConfig) -> R64 {
+        decayed_pressure(self.pressure, self.last_update.elapsed(), conf)
+    }
+
+    /// The wall-clock time of the last update. [`CacheInstant`]s don't survive restarts, so this is what gets persisted.
+    pub fn updated_at(&self) -> chrono::DateTime<Utc> {
+        let elapsed =
+            chrono::Duration::from_std(self.last_update.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+        Utc::now() - elapsed
+    }
+
+    /// Restores persisted pressure, decaying it by however long it's been since it was stored.
+    /// Responses for the rung the pressure is still on were already applied, so they aren't applied again.
+    pub fn restore(pressure: R64, updated_at: chrono::DateTime<Utc>, conf: &SpamConfig) -> UserPressure {
+        let elapsed = (Utc::now() - updated_at).to_std().unwrap_or_default();
+        let pressure = decayed_pressure(pressure, elapsed, conf);
+        Self {
+            rung: conf.rung_for(pressure),
+            ..Self::with_pressure(pressure)
+        }
+    }
+}
+
+/// Applies `elapsed` worth of decay to a pressure value.
+fn decayed_pressure(pressure: R64, elapsed: time::Duration, conf: &SpamConfig) -> R64 {
+    if conf.pressure_decay == 0.0 || pressure == 0.0 {
+        return pressure;
+    }
+
+    let decay = R64::try_new(elapsed.as_secs_f64()).unwrap_or_else(R64::zero).raw()
+        / conf.pressure_decay.raw().clamp(0.0, f64::MAX);
+    let decay = decay * conf.base_pressure.raw();
+    R64::try_new((pressure.raw() - decay).clamp(0.0, f64::MAX)).unwrap_or_else(R64::zero)
+}
+
+/// Wrapper around DbContext to persist user pressure across restarts.
+#[derive(Shrinkwrap)]
+pub struct SpamPressures<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> SpamPressures<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        SpamPressures {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Loads all persisted pressure for the guild, along with when it was last updated.
+    pub async fn load(&self) -> crate::error::Result<Vec<(UserId, R64, chrono::DateTime<Utc>)>> {
+        let rows = self.ctx.storage().load_spam_pressure(self.ctx.guild()).await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(u, p, t)| R64::try_new(p).map(|p| (u, p, t)))
+            .collect())
+    }
+
+    /// Replaces all persisted pressure for the guild with the given snapshot.
+    pub async fn replace_all(&self, pressures: &[(UserId, UserPressure)]) -> crate::error::Result<()> {
+        let pressures: Vec<StoredPressure> = pressures
+            .iter()
+            .map(|(u, p)| (*u, p.pressure().raw(), p.updated_at()))
+            .collect();
+        self.ctx
+            .storage()
+            .replace_spam_pressure(self.ctx.guild(), &pressures)
+            .await
+    }
 }
 
 impl Default for SpamConfig {
@@ -148,50 +476,419 @@ impl Default for SpamConfig {
             max_pressure: R64::new(DEFAULT_MAX_PRESSURE),
             ping_pressure: R64::new(DEFAULT_PING_PRESSURE),
             pressure_decay: R64::new(DEFAULT_PRESSURE_DECAY),
+            file_pressure: default_file_pressure(),
+            attachment_size_pressure: default_attachment_size_pressure(),
+            sticker_pressure: default_sticker_pressure(),
+            emoji_pressure: default_emoji_pressure(),
+            embed_pressure: default_embed_pressure(),
             silence_timeout: DEFAULT_SILENCE_TIMEOUT,
+            responses: Vec::new(),
+            repeat_mutes: None,
+            role_multipliers: Vec::new(),
+            risk_multiplier: R64::zero(),
         }
     }
 }
 
-/// Calculates the message pressure of a single message.
-pub fn message_pressure(conf: &SpamConfig, msg: &Message) -> R64 {
-    let mut pres = conf.base_pressure.raw();
+/// The pressure generated by a single message, split up by the factor that generated it.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PressureBreakdown {
+    /// Pressure from sending a message at all.
+    pub base: f64,
+    /// The number of images in the message.
+    pub images: usize,
+    /// Pressure from images.
+    pub image: f64,
+    /// The number of attachments which aren't images.
+    pub files: usize,
+    /// Pressure from attachments which aren't images.
+    pub file: f64,
+    /// The total size of all attachments in bytes.
+    pub attachment_bytes: u64,
+    /// Pressure from attachment size.
+    pub attachment_size: f64,
+    /// The length of the message in bytes.
+    pub length_bytes: usize,
+    /// Pressure from message length.
+    pub length: f64,
+    /// The number of user, role and everyone pings in the message.
+    pub pings: usize,
+    /// Pressure from pings.
+    pub ping: f64,
+    /// The number of line breaks in the message.
+    pub lines: usize,
+    /// Pressure from line breaks.
+    pub line: f64,
+    /// The number of stickers in the message.
+    pub stickers: usize,
+    /// Pressure from stickers.
+    pub sticker: f64,
+    /// The number of custom emoji in the message.
+    pub emojis: usize,
+    /// Pressure from custom emoji.
+    pub emoji: f64,
+    /// The number of link embeds in the message.
+    pub embeds: usize,
+    /// Pressure from link embeds.
+    pub embed: f64,
+}
+
+/// The number of bytes in a MiB.
+const BYTES_PER_MIB: f64 = 1024.0 * 1024.0;
+
+impl PressureBreakdown {
+    /// Scores a message per factor.
+    pub fn new(conf: &SpamConfig, msg: &Message) -> Self {
+        let images = msg.attachments.iter().filter(|a| a.height.is_some()).count();
+        let files = msg.attachments.len() - images;
+        let attachment_bytes = msg.attachments.iter().map(|a| a.size).sum::<u64>();
+        let length_bytes = msg.content.len();
+        let pings = msg.mentions.len() + msg.mention_roles.len() + msg.mention_everyone as usize;
+        let lines = VERTICAL_WHITESPACE_RE.find_iter(&msg.content).count();
+        let stickers = msg.stickers.len();
+        let emojis = CUSTOM_EMOJI_RE.find_iter(&msg.content).count();
+        // Embeds are usually added after the message is sent, so count the links that will produce them too.
+        let embeds = msg.embeds.len().max(EMBED_LINK_RE.find_iter(&msg.content).count());
+
+        Self {
+            base: conf.base_pressure.raw(),
+            images,
+            image: images as f64 * conf.image_pressure.raw(),
+            files,
+            file: files as f64 * conf.file_pressure.raw(),
+            attachment_bytes,
+            attachment_size: attachment_bytes as f64 / BYTES_PER_MIB * conf.attachment_size_pressure.raw(),
+            length_bytes,
+            length: length_bytes as f64 * conf.length_pressure.raw(),
+            pings,
+            ping: pings as f64 * conf.ping_pressure.raw(),
+            lines,
+            line: lines as f64 * conf.line_pressure.raw(),
+            stickers,
+            sticker: stickers as f64 * conf.sticker_pressure.raw(),
+            emojis,
+            emoji: emojis as f64 * conf.emoji_pressure.raw(),
+            embeds,
+            embed: embeds as f64 * conf.embed_pressure.raw(),
+        }
+    }
+
+    /// The total pressure generated by the message.
+    pub fn total(&self) -> R64 {
+        let total = self.base
+            + self.image
+            + self.file
+            + self.attachment_size
+            + self.length
+            + self.ping
+            + self.line
+            + self.sticker
+            + self.emoji
+            + self.embed;
+        R64::try_new(total).unwrap_or_else(R64::max_value)
+    }
+}
+
+impl fmt::Display for PressureBreakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<8} {:>8} {:>10}", "factor", "count", "pressure")?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "base", 1, self.base)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "image", self.images, self.image)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "file", self.files, self.file)?;
+        writeln!(
+            f,
+            "{:<8} {:>8} {:>10.3}",
+            "size", self.attachment_bytes, self.attachment_size
+        )?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "length", self.length_bytes, self.length)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "ping", self.pings, self.ping)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "line", self.lines, self.line)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "sticker", self.stickers, self.sticker)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "emoji", self.emojis, self.emoji)?;
+        writeln!(f, "{:<8} {:>8} {:>10.3}", "embed", self.embeds, self.embed)?;
+        write!(f, "{:<8} {:>8} {:>10.3}", "total", "", self.total().raw())
+    }
+}
+
+/// The roles of the author of a message, if the message came with member information.
+fn member_roles(msg: &Message) -> &[RoleId] {
+    msg.member.as_ref().map_or(&[], |m| m.roles.as_slice())
+}
 
-    // Add image pressure.
-    pres += msg
-        .attachments
-        .iter()
-        .filter_map(|a| a.height.map(|_| conf.image_pressure.raw()))
-        .sum::<f64>();
+/// Retrieves the risk score of a message's author, or zero if the config doesn't use risk.
+async fn member_risk(dis: &Dispatch, conf: &SpamConfig, msg: &Message) -> crate::error::Result<R64> {
+    if !conf.uses_risk() {
+        return Ok(R64::zero());
+    }
+    let joined_at = msg.member.as_ref().and_then(|m| m.joined_at);
+    let score = risk_score(
+        dis,
+        msg.guild_id.unwrap(),
+        msg.author.id,
+        joined_at,
+        conf.max_pressure.raw(),
+    )
+    .await?;
+    Ok(R64::new(score.total()))
+}
 
-    // Length pressure.
-    pres += msg.content.len() as f64 * conf.length_pressure.raw();
+/// The pressure multiplier for a member with the given risk score.
+fn risk_factor(conf: &SpamConfig, risk: R64) -> R64 {
+    R64::new(1.0) + risk * conf.risk_multiplier
+}
 
-    // Pings.
-    pres += ((msg.mentions.len() + msg.mention_roles.len()) as f64 + msg.mention_everyone as u64 as f64)
-        * conf.ping_pressure.raw();
+/// Calculates the message pressure of a single message.
+pub fn message_pressure(conf: &SpamConfig, msg: &Message) -> R64 {
+    PressureBreakdown::new(conf, msg).total()
+}
 
-    // Line pressure.
-    pres += VERTICAL_WHITESPACE_RE.find_iter(&msg.content).count() as f64 * conf.line_pressure.raw();
+/// The pressure of users in a single guild.
+pub type GuildPressure = Cache<UserId, UserPressure, LruEvictionStrategy>;
 
-    R64::try_new(pres).unwrap_or_else(R64::max_value)
+/// Creates an empty, capacity-bounded pressure cache for a guild.
+fn guild_pressure_cache() -> GuildPressure {
+    Cache::new(LruEvictionStrategy::new(PRESSURE_CACHE_CAPACITY))
 }
 
 /// Module containing the spam filtering logic for Glimbot.
 pub struct SpamModule {
-    cache: TimedCache<GuildId, SpamConfig>,
-    user_pressure: Cache<GuildId, Cache<UserId, UserPressure>>,
+    user_pressure: Arc<Cache<GuildId, GuildPressure>>,
+    /// Pressure calculated with the candidate [`SPAM_SHADOW_CONFIG`]; kept separate from the live pressure.
+    shadow_pressure: Cache<GuildId, GuildPressure>,
+    /// When users were recently muted for spam, for [`RepeatMutes`].
+    mute_history: Cache<GuildId, Cache<UserId, Vec<CacheInstant>, LruEvictionStrategy>>,
+    last_persist: Mutex<CacheInstant>,
 }
 
 impl Default for SpamModule {
     fn default() -> Self {
         Self {
-            cache: TimedCache::new(std::time::Duration::from_secs(10)),
-            user_pressure: Cache::null(),
+            user_pressure: Arc::new(Cache::null()),
+            shadow_pressure: Cache::null(),
+            mute_history: Cache::null(),
+            last_persist: Mutex::new(CacheInstant::now()),
         }
     }
 }
 
+/// A read-only handle to the live pressure tracked by a [`SpamModule`], so other modules can display it.
+#[derive(Clone)]
+pub struct PressureView(Arc<Cache<GuildId, GuildPressure>>);
+
+impl PressureView {
+    /// The current pressure of a user, if Glimbot has seen them since it last started.
+    pub fn current_pressure(&self, guild: GuildId, user: UserId, conf: &SpamConfig) -> Option<R64> {
+        self.0.get(&guild)?.get(&user).map(|p| p.current_pressure(conf))
+    }
+}
+
+impl SpamModule {
+    /// Creates a handle for reading the pressure this module tracks.
+    pub fn pressure_view(&self) -> PressureView {
+        PressureView(self.user_pressure.clone())
+    }
+
+    /// Retrieves the spam config for a guild. This goes through the dispatch's config cache, and nothing else,
+    /// so changes, including those announced by other processes, apply to the very next message.
+    async fn spam_config(&self, dis: &Dispatch, gid: GuildId) -> crate::error::Result<SpamConfig> {
+        let db = dis.db(gid);
+        let v = dis.config_value_t::<SpamConfig>(SPAM_CONFIG_KEY)?;
+        Ok(v.get_or_default(&db).await?.as_ref().clone())
+    }
+
+    /// Retrieves the pressure of users in a guild, restoring any persisted pressure the first time the guild is seen.
+    async fn guild_pressure(&self, dis: &Dispatch, gid: GuildId) -> crate::error::Result<Cached<GuildPressure, ()>> {
+        let f = async {
+            let conf = self.spam_config(dis, gid).await?;
+            let pressures = guild_pressure_cache();
+            let stored = SpamPressures::new(dis.db(gid)).load().await?;
+            debug!("restoring pressure for {} user(s)", stored.len());
+            for (user, pressure, updated_at) in stored {
+                pressures.insert(&user, UserPressure::restore(pressure, updated_at, &conf));
+            }
+            Ok(pressures)
+        };
+        self.user_pressure.get_or_insert_with(&gid, f).await
+    }
+
+    /// Tracks pressure under the candidate config, reporting to the mod log when it would have acted.
+    async fn shadow_candidate(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        candidate: &SpamConfig,
+        orig: &Message,
+        live_acted: bool,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let risk = member_risk(dis, candidate, orig).await?;
+        let lp = message_pressure(candidate, orig)
+            * candidate.role_multiplier(ctx, gid, member_roles(orig)).await
+            * risk_factor(candidate, risk);
+        let pres_cache = self.shadow_pressure.get_or_insert_sync(&gid, guild_pressure_cache);
+        let upd = pres_cache.update(&orig.author.id, |o| {
+            let o = o.cloned().unwrap_or_else(Default::default);
+            Some(o.update(lp, candidate))
+        });
+        let prev = upd.old.and_then(|o| o.current_rung(candidate));
+        let pres = upd.new.unwrap();
+        trace!("candidate pressure is {:?}", pres.as_ref());
+
+        if let Some(resp) = candidate
+            .response_for(prev, pres.rung, risk)
+            .filter(|_| pres.rung > prev)
+        {
+            report_shadow_response(
+                dis,
+                ctx,
+                pres.pressure,
+                orig,
+                resp,
+                ShadowSource::Candidate { live_acted },
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records that a user was muted for spam, returning the escalation to apply instead if they've been
+    /// muted too often recently.
+    fn record_mute(&self, gid: GuildId, user: UserId, conf: &SpamConfig) -> Option<RepeatMutes> {
+        let rep = conf.repeat_mutes?;
+        let history = self
+            .mute_history
+            .get_or_insert_sync(&gid, || Cache::new(LruEvictionStrategy::new(MUTE_HISTORY_CAPACITY)));
+        let mutes = history
+            .update_and_fetch(&user, |o| {
+                // Mutes from before the window no longer count, so don't keep them around.
+                let mut v: Vec<CacheInstant> = o
+                    .map(|v| v.iter().copied().filter(|t| t.elapsed() < rep.window).collect())
+                    .unwrap_or_default();
+                v.push(CacheInstant::now());
+                Some(v)
+            })
+            .unwrap();
+
+        if mutes.len() >= rep.count as usize {
+            history.insert(&user, Vec::new());
+            Some(rep)
+        } else {
+            None
+        }
+    }
+
+    /// Applies a rung of the response ladder to the author of a message, through [`ModAction`], and reports it
+    /// in the mod log.
+    /// Returns false if the author is exempt from spam responses.
+    async fn respond_to_spam(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        conf: &SpamConfig,
+        orig: &Message,
+        resp: SpamResponse,
+    ) -> crate::error::Result<bool> {
+        if exempt_from_spam(dis, ctx, orig).await? {
+            return Ok(false);
+        }
+
+        let gid = orig.guild_id.unwrap();
+        let (action, duration, reason) = match resp.action {
+            SpamAction::Mute => match self.record_mute(gid, orig.author.id, conf) {
+                Some(r) => (r.action, r.duration, "Repeated spam"),
+                None => (resp.action, resp.duration, "Spam"),
+            },
+            _ => (resp.action, resp.duration, "Spam"),
+        };
+
+        if action == SpamAction::Warn {
+            let guild_name = gid.name(ctx).await.unwrap_or_else(|| "the server".to_string());
+            orig.author
+                .direct_message(ctx, |m| {
+                    m.content(format!(
+                        "You're sending messages too quickly in {}. Please slow down, or you may be muted.",
+                        guild_name
+                    ))
+                })
+                .await
+                .map_err(crate::error::Error::from)
+                .log_error();
+        }
+
+        let kind = action.action_kind();
+        let full_mem = orig.member(ctx).await?;
+        let me = dis.bot().await;
+        let mod_action = ModAction::new(full_mem, orig.channel_id, me, kind)
+            .with_duration(duration.filter(|_| kind.has_duration()).map(Into::into))
+            .with_reason(reason)
+            .with_original_message(orig.id);
+        mod_action.act(dis, ctx).await?;
+        // The action was taken either way, so a missing mod log shouldn't make it look like it wasn't.
+        mod_action.report_action(dis, ctx).await.log_error();
+
+        if matches!(action, SpamAction::Warn | SpamAction::Mute) {
+            // tell em to shut up
+            orig.react(ctx, Unicode("⚠️".to_string()))
+                .await
+                .map_err(crate::error::Error::from)
+                .log_error();
+        }
+
+        Ok(true)
+    }
+
+    /// Removes expired entries from the module's caches, and users who haven't been seen in a while from guilds
+    /// with too many users to track.
+    fn sweep_caches(&self) {
+        let mut swept = 0;
+        for pressures in [&*self.user_pressure, &self.shadow_pressure].iter() {
+            swept += pressures.sweep();
+            for (gid, users) in pressures.entries() {
+                swept += users.sweep();
+                trace!("pressure cache for {}: {:?}", gid, users.metrics());
+            }
+        }
+        for (_, users) in self.mute_history.entries() {
+            swept += users.sweep();
+        }
+        if swept > 0 {
+            debug!("swept {} spam cache entries", swept);
+        }
+    }
+
+    /// Writes the pressure of every user with non-zero pressure to the database.
+    /// A guild which fails to persist is logged and skipped, so it doesn't hold up the others.
+    async fn persist_pressure(&self, dis: &Dispatch) {
+        *self.last_persist.lock() = CacheInstant::now();
+        for (gid, users) in self.user_pressure.entries() {
+            self.persist_guild_pressure(dis, gid, &users).await.log_error();
+        }
+    }
+
+    /// Writes the pressure of every user in a guild with non-zero pressure to the database.
+    async fn persist_guild_pressure(
+        &self,
+        dis: &Dispatch,
+        gid: GuildId,
+        users: &GuildPressure,
+    ) -> crate::error::Result<()> {
+        let conf = self.spam_config(dis, gid).await?;
+        let snapshot: Vec<(UserId, UserPressure)> = users
+            .entries()
+            .into_iter()
+            .map(|(u, p)| (u, *p))
+            .filter(|(_, p)| p.current_pressure(&conf) > 0.0)
+            .collect();
+        trace!("persisting pressure for {} user(s) in {}", snapshot.len(), gid);
+        SpamPressures::new(dis.db(gid)).replace_all(&snapshot).await?;
+
+        // Pressure is only ever decayed lazily, so the stored value is the peak since the user's last message.
+        let peaks: Vec<(UserId, f64)> = snapshot.iter().map(|(u, p)| (*u, p.pressure().raw())).collect();
+        MemberRisk::new(dis.db(gid)).record_peak_pressures(&peaks).await
+    }
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, structopt::StructOpt)]
 enum PressureOp {
@@ -206,34 +903,139 @@ enum PressureOp {
     ClearFor {
         user: String,
     },
+    /// Lists the users with the highest pressure in the guild.
+    Top {
+        #[structopt(default_value = "10")]
+        n: ConstrainedU64<1, MAX_PRESSURE_TOP>,
+    },
 }
 
 impl PressureOp {
-    pub fn user(&self) -> &str {
+    pub fn user(&self) -> Option<&str> {
         match self {
-            PressureOp::GetFor { user } => user.as_ref(),
-            PressureOp::SetFor { user, .. } => user.as_ref(),
-            PressureOp::ClearFor { user } => user.as_ref(),
+            PressureOp::GetFor { user } => Some(user.as_ref()),
+            PressureOp::SetFor { user, .. } => Some(user.as_ref()),
+            PressureOp::ClearFor { user } => Some(user.as_ref()),
+            PressureOp::Top { .. } => None,
+        }
+    }
+}
+
+/// Filters restricting which messages `spam clean` deletes.
+#[derive(Debug, Default, structopt::StructOpt)]
+pub struct CleanFilters {
+    /// Only delete messages sent by bots.
+    #[structopt(long)]
+    pub bots: bool,
+    /// Only delete messages containing this text (case insensitive).
+    #[structopt(long)]
+    pub contains: Option<String>,
+    /// Only delete messages matching this regular expression.
+    #[structopt(long, parse(try_from_str = Regex::new))]
+    pub regex: Option<Regex>,
+    /// Only delete messages with attachments.
+    #[structopt(long)]
+    pub attachments: bool,
+    /// Only delete messages sent after this message (ID or link).
+    #[structopt(long)]
+    pub after: Option<MessageRef>,
+}
+
+impl CleanFilters {
+    /// Checks whether a message passes all of the content filters.
+    pub fn matches(&self, msg: &Message) -> bool {
+        if self.bots && !msg.author.bot {
+            return false;
+        }
+
+        if self.attachments && msg.attachments.is_empty() {
+            return false;
+        }
+
+        if let Some(needle) = &self.contains {
+            if !msg.content.to_lowercase().contains(&needle.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if let Some(re) = &self.regex {
+            if !re.is_match(&msg.content) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl fmt::Display for CleanFilters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.bots {
+            write!(f, " from bots")?;
+        }
+        if let Some(needle) = &self.contains {
+            write!(f, " containing {:?}", needle)?;
+        }
+        if let Some(re) = &self.regex {
+            write!(f, " matching /{}/", re)?;
+        }
+        if self.attachments {
+            write!(f, " with attachments")?;
         }
+        if let Some(after) = &self.after {
+            write!(f, " after message {}", after.message)?;
+        }
+        Ok(())
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, structopt::StructOpt)]
 enum SpamOpts {
     Clean {
         #[structopt(default_value = "1")]
-        num: ConstrainedU64<1, 100>,
+        num: ConstrainedU64<1, MAX_CLEAN>,
         #[structopt(short, long)]
         channel: Option<String>,
         #[structopt(short, long)]
         who: Option<String>,
+        #[structopt(flatten)]
+        filters: CleanFilters,
     },
     Pressure {
         #[structopt(subcommand)]
         op: PressureOp,
     },
+    /// Shows how much pressure a message generated per factor, and its author's pressure now.
+    Explain {
+        /// The message, as a link or an ID in this channel.
+        message: MessageRef,
+    },
 }
 
+/// Constraints on fields of [`SpamConfig`], shared by the live and shadow configs.
+const SPAM_CONFIG_CONSTRAINTS: [FieldConstraint; 19] = [
+    FieldConstraint::at_least("base_pressure", 0.0),
+    FieldConstraint::at_least("image_pressure", 0.0),
+    FieldConstraint::at_least("length_pressure", 0.0),
+    FieldConstraint::at_least("line_pressure", 0.0),
+    FieldConstraint::at_least("max_pressure", 0.0),
+    FieldConstraint::at_least("ping_pressure", 0.0),
+    FieldConstraint::at_least("pressure_decay", 0.0),
+    FieldConstraint::at_least("file_pressure", 0.0),
+    FieldConstraint::at_least("attachment_size_pressure", 0.0),
+    FieldConstraint::at_least("sticker_pressure", 0.0),
+    FieldConstraint::at_least("emoji_pressure", 0.0),
+    FieldConstraint::at_least("embed_pressure", 0.0),
+    FieldConstraint::at_least("risk_multiplier", 0.0),
+    FieldConstraint::at_least("responses.*.pressure", 0.0),
+    FieldConstraint::range("responses.*.min_risk", 0.0, 1.0),
+    FieldConstraint::one_of("responses.*.action", &["warn", "delete", "mute", "kick", "ban"]),
+    FieldConstraint::at_least("role_multipliers.*.multiplier", 0.0),
+    FieldConstraint::at_least("repeat_mutes.count", 1.0),
+    FieldConstraint::one_of("repeat_mutes.action", &["kick", "ban"]),
+];
+
 #[async_trait::async_trait]
 impl Module for SpamModule {
     fn info(&self) -> &ModInfo {
@@ -243,9 +1045,12 @@ impl Module for SpamModule {
                 .with_sensitivity(Sensitivity::High)
                 .with_message_hook(true)
                 .with_tick_hook(true)
+                .with_shutdown_hook(true)
                 .with_command(true)
-                .with_config_value(config::Value::<VerifiedRole>::new(SPAM_IGNORE_ROLE, "A role which should be ignored for spam pressure calculations. The guild owner and moderators will not generate pressure."))
-                .with_config_value(config::Value::<SpamConfig>::with_default(SPAM_CONFIG_KEY, "A JSON object describing various options for calculating spam pressure. See Glimbot's documentation for more info.", Default::default))
+                .with_config_value(config::Value::<VerifiedRole>::new(SPAM_IGNORE_ROLE, "A role which should be ignored for spam pressure calculations. The guild owner and moderators will not generate pressure.").with_clearable(true).with_example("Trusted"))
+                .with_config_value(config::Value::<SpamConfig>::with_default(SPAM_CONFIG_KEY, "A JSON object describing various options for calculating spam pressure. See Glimbot's documentation for more info.", Default::default).with_constraints(SPAM_CONFIG_CONSTRAINTS))
+                .with_config_value(config::Value::<bool>::with_default(SPAM_SHADOW_MODE, "If true, Glimbot logs who it would have muted for spam to the mod log instead of muting them.", Default::default).with_example("true"))
+                .with_config_value(config::Value::<SpamConfig>::new(SPAM_SHADOW_CONFIG, "A candidate spam_config which is run in shadow alongside the live one; who it would have muted is logged to the mod log.").with_clearable(true).with_constraints(SPAM_CONFIG_CONSTRAINTS))
         });
         &INFO
     }
@@ -259,64 +1064,156 @@ impl Module for SpamModule {
     ) -> crate::error::Result<()> {
         let opts = SpamOpts::from_iter_with_help(command)?;
 
-        let before = orig.timestamp;
         let gid = orig.guild_id.unwrap();
 
         match opts {
-            SpamOpts::Clean { num, channel, who } => {
-                let channel = futures::stream::iter(channel.as_ref())
-                    .then(|c| VerifiedChannel::from_str_with_ctx(&c, ctx, gid))
-                    .next()
-                    .await
-                    .transpose()?
-                    .unwrap_or_else(|| VerifiedChannel::from_known(orig.channel_id));
+            SpamOpts::Clean {
+                num,
+                channel,
+                who,
+                filters,
+            } => {
+                let channel = match &channel {
+                    Some(c) => VerifiedChannel::from_str_with_ctx(c, ctx, gid).await?,
+                    None => VerifiedChannel::from_known(orig.channel_id),
+                };
 
-                let who = futures::stream::iter(who.as_ref())
-                    .then(|u| VerifiedUser::from_str_with_ctx(&u, ctx, gid))
-                    .next()
+                let who = match &who {
+                    Some(u) => Some(VerifiedUser::from_str_with_ctx(u, ctx, gid).await?),
+                    None => orig
+                        .referenced_message
+                        .as_ref()
+                        .map(|m| VerifiedUser::from_known(m.author.id)),
+                };
+
+                let summary = format!(
+                    "Delete up to {} message(s) in {}{}{}",
+                    num,
+                    channel.to_channel_name_or_id(ctx, gid).await,
+                    match who {
+                        Some(u) => format!(" from {}", u.to_user_name_or_id(ctx, gid).await),
+                        None => String::new(),
+                    },
+                    filters
+                );
+                if !confirm_if_required(dis, ctx, orig, "spam clean", summary).await? {
+                    return Ok(());
+                }
+
+                let cleaned = clean_messages(dis, ctx, num, orig.id, gid, channel, who, &filters).await?;
+                archive_cleaned_messages(dis, ctx, gid, channel, orig, &filters, &cleaned.deleted)
                     .await
-                    .transpose()?
-                    .or_else(|| {
-                        orig.referenced_message
-                            .as_ref()
-                            .map(|m| VerifiedUser::from_known(m.author.id))
-                    });
-
-                let num_cleaned = clean_messages(dis, ctx, num, before, gid, channel, who).await?;
-                orig.reply(ctx, format!("```Cleaned {} message(s)```", num_cleaned))
+                    .log_error();
+                if let Some(e) = cleaned.error {
+                    return Err(e);
+                }
+                orig.reply(ctx, format!("```Cleaned {} message(s)```", cleaned.deleted.len()))
+                    .await?;
+            }
+            SpamOpts::Pressure {
+                op: PressureOp::Top { n },
+            } => {
+                let conf = self.spam_config(dis, gid).await?;
+                let pressures = self.guild_pressure(dis, gid).await?;
+                let mut top: Vec<(UserId, R64)> = pressures
+                    .entries()
+                    .into_iter()
+                    .map(|(u, p)| (u, p.current_pressure(&conf)))
+                    .filter(|(_, p)| *p > 0.0)
+                    .collect();
+                top.sort_unstable_by_key(|(_, p)| std::cmp::Reverse(*p));
+                top.truncate(n.to_usize().unwrap());
+
+                let mut out = format!("max pressure: {:.3}\n", conf.max_pressure.raw());
+                if top.is_empty() {
+                    out.push_str("No users currently have pressure.");
+                }
+                for (i, (u, p)) in top.into_iter().enumerate() {
+                    let name = VerifiedUser::from_known(u).to_user_name_or_id(ctx, gid).await;
+                    out.push_str(&format!("{:>2}. {:>10.3} {}\n", i + 1, p.raw(), name));
+                }
+
+                orig.reply(ctx, MessageBuilder::new().push_codeblock_safe(out, None).build())
                     .await?;
             }
             SpamOpts::Pressure { op } => {
-                let user = VerifiedUser::from_str_with_ctx(op.user(), ctx, gid).await?;
+                let user = op.user().expect("every other pressure op targets a user");
+                let user = VerifiedUser::from_str_with_ctx(user, ctx, gid).await?;
+                let pressures = self.guild_pressure(dis, gid).await?;
                 match op {
                     PressureOp::GetFor { .. } => {
-                        let pres = self
-                            .user_pressure
-                            .get_or_insert_default(&gid)
-                            .get_or_insert_default(&user.into_inner());
+                        let pres = pressures.get_or_insert_default(&user.into_inner());
 
                         orig.reply(ctx, format!("`{}`", pres.pressure)).await?;
                     }
                     PressureOp::SetFor { pressure, .. } => {
-                        self.user_pressure
-                            .get_or_insert_default(&gid)
-                            .insert(&user.into_inner(), UserPressure::with_pressure(pressure));
+                        pressures.insert(&user.into_inner(), UserPressure::with_pressure(pressure));
                         orig.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
                     }
                     PressureOp::ClearFor { .. } => {
-                        self.user_pressure
-                            .get_or_insert_default(&gid)
-                            .insert(&user.into_inner(), UserPressure::default());
+                        pressures.insert(&user.into_inner(), UserPressure::default());
                         orig.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
                     }
+                    PressureOp::Top { .. } => unreachable!("handled above"),
                 }
             }
+            SpamOpts::Explain { message } => {
+                let channel = message.channel.unwrap_or(orig.channel_id);
+                let channel = VerifiedChannel::from_id(channel, ctx, gid).await?.into_inner();
+                let msg = channel.message(ctx, message.message).await?;
+                let conf = self.spam_config(dis, gid).await?;
+                let breakdown = PressureBreakdown::new(&conf, &msg);
+                let (roles, joined_at) = match gid.member(ctx, msg.author.id).await {
+                    Ok(m) => (m.roles, m.joined_at),
+                    Err(_) => (Vec::new(), None),
+                };
+                let multiplier = conf.role_multiplier(ctx, gid, &roles).await;
+                let risk = if conf.uses_risk() {
+                    let score = risk_score(dis, gid, msg.author.id, joined_at, conf.max_pressure.raw()).await?;
+                    risk_factor(&conf, R64::new(score.total()))
+                } else {
+                    R64::new(1.0)
+                };
+                let now = self
+                    .guild_pressure(dis, gid)
+                    .await?
+                    .get(&msg.author.id)
+                    .map(|p| p.current_pressure(&conf))
+                    .unwrap_or_else(R64::zero);
+
+                let out = format!(
+                    "{}\n{:<8} {:>8} {:>10.3}\n{:<8} {:>8.3} {:>10.3}\n\n{} pressure now: {:.3} / {:.3}",
+                    breakdown,
+                    "x role",
+                    multiplier.raw(),
+                    (breakdown.total() * multiplier).raw(),
+                    "x risk",
+                    risk.raw(),
+                    (breakdown.total() * multiplier * risk).raw(),
+                    msg.author.tag(),
+                    now.raw(),
+                    conf.max_pressure.raw()
+                );
+                orig.reply(ctx, MessageBuilder::new().push_codeblock_safe(out, None).build())
+                    .await?;
+            }
         }
 
         Ok(())
     }
 
-    async fn on_tick(&self, _dis: &Dispatch, _ctx: &Context) -> crate::error::Result<()> {
+    async fn on_tick(&self, dis: &Dispatch, _ctx: &Context) -> crate::error::Result<()> {
+        self.sweep_caches();
+        if self.last_persist.lock().elapsed() < PRESSURE_PERSIST_INTERVAL {
+            return Ok(());
+        }
+        self.persist_pressure(dis).await;
+        Ok(())
+    }
+
+    async fn on_shutdown(&self, dis: &Dispatch) -> crate::error::Result<()> {
+        info!("persisting spam pressure");
+        self.persist_pressure(dis).await;
         Ok(())
     }
 
@@ -330,33 +1227,48 @@ impl Module for SpamModule {
         };
 
         let start = std::time::Instant::now();
-        let f = async {
-            let db = dis.db(gid);
-            let v = dis.config_value_t::<SpamConfig>(SPAM_CONFIG_KEY).unwrap();
-            Ok(*v.get_or_default(&db).await?)
-        };
-        let conf = self.cache.get_or_insert_with(&gid, f).await?;
+        let conf = self.spam_config(dis, gid).await?;
+        let pres_cache = self.guild_pressure(dis, gid).await?;
         let pre_mess = start.elapsed();
-        let lp = message_pressure(&conf, orig);
+        let risk = member_risk(dis, &conf, orig).await?;
+        let lp = message_pressure(&conf, orig)
+            * conf.role_multiplier(ctx, gid, member_roles(orig)).await
+            * risk_factor(&conf, risk);
 
-        let pres_cache = self.user_pressure.get_or_insert_default(&gid);
-        let pres = pres_cache
-            .update_and_fetch(&orig.author.id, |o| {
-                let o = o.cloned().unwrap_or_else(Default::default);
-                Some(o.update(lp, &conf))
-            })
-            .unwrap();
+        let upd = pres_cache.update(&orig.author.id, |o| {
+            let o = o.cloned().unwrap_or_else(Default::default);
+            Some(o.update(lp, &conf))
+        });
+        let prev = upd.old.and_then(|o| o.current_rung(&conf));
+        let pres = upd.new.unwrap();
+
+        let db = dis.db(gid);
+        let shadow_mode = *dis
+            .config_value_t::<bool>(SPAM_SHADOW_MODE)?
+            .get_or_default(&db)
+            .await?;
 
-        if pres.pressure > conf.max_pressure {
-            let r = mute_for_spam(dis, ctx, conf.as_ref(), orig).await;
-            r.log_error();
-            if let Ok(true) = r {
-                // tell em to shut up
-                orig.react(ctx, Unicode("⚠️".to_string()))
+        let response = conf.response_for(prev, pres.rung, risk);
+        let live_acted = match response {
+            // Only report climbing the ladder in shadow mode, or repeated deletions would flood the mod log.
+            Some(resp) if shadow_mode && pres.rung > prev => {
+                report_shadow_response(dis, ctx, pres.pressure, orig, resp, ShadowSource::Live)
                     .await
-                    .map_err(crate::error::Error::from)
                     .log_error();
+                false
             }
+            Some(resp) if !shadow_mode => {
+                let res = self.respond_to_spam(dis, ctx, &conf, orig, resp).await;
+                res.log_error();
+                matches!(res, Ok(true))
+            }
+            _ => false,
+        };
+
+        if let Some(candidate) = dis.config_value_t::<SpamConfig>(SPAM_SHADOW_CONFIG)?.get(&db).await? {
+            self.shadow_candidate(dis, ctx, &candidate, orig, live_acted)
+                .await
+                .log_error();
         }
 
         let finish = start.elapsed();
@@ -371,12 +1283,14 @@ impl Module for SpamModule {
     }
 }
 
-async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &Message) -> crate::error::Result<bool> {
+/// Checks whether the author of a message is exempt from automatic spam responses, i.e. because
+/// they're the guild owner, a moderator or have the [`SPAM_IGNORE_ROLE`].
+async fn exempt_from_spam(dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<bool> {
     // Ignore if this is the guild owner.
     let guild = orig.guild(ctx).await.ok_or(GuildNotInCache)?;
     if guild.owner_id == orig.author.id {
         trace!("not muting guild owner");
-        return Ok(false);
+        return Ok(true);
     }
     let db = dis.db(guild.id);
 
@@ -387,7 +1301,7 @@ async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &
         let r = *r;
         if mem.roles.contains(&r.into_inner()) {
             trace!("not muting moderator");
-            return Ok(false);
+            return Ok(true);
         }
     }
 
@@ -397,64 +1311,323 @@ async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &
         let r = *r;
         if mem.roles.contains(&r.into_inner()) {
             trace!("not muting ignore role");
-            return Ok(false);
+            return Ok(true);
         }
     }
 
-    let duration = if conf.silence_timeout > Duration::from_secs(0) {
-        Some(conf.silence_timeout.into())
-    } else {
-        None
+    Ok(false)
+}
+
+/// Which config a shadow mode report was generated by.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ShadowSource {
+    /// The live [`SPAM_CONFIG_KEY`] config, with [`SPAM_SHADOW_MODE`] turned on.
+    Live,
+    /// The candidate [`SPAM_SHADOW_CONFIG`] config. Holds whether the live config acted on the same message.
+    Candidate { live_acted: bool },
+}
+
+/// Logs what an automatic spam response would have done to the mod log, without doing it.
+/// Returns false if the author is exempt from spam responses.
+async fn report_shadow_response(
+    dis: &Dispatch,
+    ctx: &Context,
+    pressure: R64,
+    orig: &Message,
+    resp: SpamResponse,
+    source: ShadowSource,
+) -> crate::error::Result<bool> {
+    if exempt_from_spam(dis, ctx, orig).await? {
+        return Ok(false);
+    }
+
+    let duration = match (resp.action, resp.duration) {
+        (SpamAction::Mute, Some(d)) | (SpamAction::Ban, Some(d)) => {
+            format!(" for {}", humantime::format_duration(d))
+        }
+        (SpamAction::Mute, None) | (SpamAction::Ban, None) => " indefinitely".to_string(),
+        _ => String::new(),
     };
+    let which = match source {
+        ShadowSource::Live => "live",
+        ShadowSource::Candidate { .. } => "candidate",
+    };
+
+    send_to_mod_log(dis, ctx, orig.guild_id.unwrap(), |e| {
+        e.title("Spam Shadow Mode")
+            .description(format!(
+                "Would have {} {}{} under the {} spam config.",
+                resp.action.past_tense(),
+                orig.author.mention(),
+                duration,
+                which
+            ))
+            .field(
+                "Pressure",
+                format!("{:.3} / {:.3}", pressure.raw(), resp.pressure.raw()),
+                true,
+            )
+            .field("Message", format!("[Jump]({})", orig.link()), true);
+        if let ShadowSource::Candidate { live_acted } = source {
+            e.field(
+                "Live Config",
+                if live_acted { "Also acted" } else { "Did not act" },
+                true,
+            );
+        }
+        e.timestamp(&Utc::now())
+    })
+    .await?;
+
+    Ok(true)
+}
+
+/// Finds messages to clean from one user without the API, if the caches cover the range. The message cache says
+/// which messages were sent, and the Discord cache holds their content. Returns `None` if either is missing
+/// anything in the range, i.e. after a restart or for a range too old to still be cached.
+#[allow(clippy::too_many_arguments)]
+async fn cached_messages_to_clean(
+    dis: &Dispatch,
+    ctx: &Context,
+    how_many: usize,
+    before: MessageId,
+    in_guild: GuildId,
+    in_channel: ChannelId,
+    for_user: Option<UserId>,
+    filters: &CleanFilters,
+) -> Option<Vec<Message>> {
+    // Glimbot's own messages aren't in the message cache, so only a single other user's messages can be
+    // found there.
+    let me = ctx.cache.current_user_id().await;
+    let user = for_user.filter(|u| *u != me)?;
+    let seen = dis.message_cache().get(&in_guild)?.snapshot();
+    let after = filters.after.map(|m| m.message);
+
+    let mut out = Vec::new();
+    // Everything newer than the oldest message still cached for the guild was seen, so the range is covered
+    // as long as the walk stops before running out of cached messages.
+    for info in seen.iter().rev().filter(|m| m.channel == in_channel && m.msg < before) {
+        if after.map_or(false, |a| info.msg <= a) {
+            return Some(out);
+        }
+        if info.user != user {
+            continue;
+        }
 
-    let full_mem = orig.member(ctx).await?;
-    let me = dis.bot().await;
-    let action = ModAction::new(full_mem, orig.channel_id, me, ActionKind::Mute)
-        .with_duration(duration)
-        .with_reason("Spam")
-        .with_original_message(orig.id);
-    action.act(dis, ctx).await?;
-    action.report_action(dis, ctx).await.map(|_| true)
+        let msg = ctx.cache.message(in_channel, info.msg).await?;
+        if filters.matches(&msg) {
+            out.push(msg);
+            if out.len() >= how_many {
+                return Some(out);
+            }
+        }
+    }
+    None
 }
 
+/// Fetches messages in a channel older than `before`, newest first, filtering them as it goes.
+/// Messages are paged from the API; this is the fallback for when the caches don't cover the range.
+async fn collect_messages_to_clean(
+    ctx: &Context,
+    how_many: usize,
+    before: MessageId,
+    in_channel: ChannelId,
+    for_user: Option<UserId>,
+    filters: &CleanFilters,
+) -> crate::error::Result<Vec<Message>> {
+    let after = filters.after.map(|m| m.message);
+    let mut out = Vec::new();
+    let mut cursor = before;
+    let mut scanned = 0usize;
+
+    while out.len() < how_many && scanned < MAX_CLEAN_SCAN {
+        let page = in_channel
+            .messages(ctx, |b| b.before(cursor).limit(HISTORY_PAGE_SIZE))
+            .await?;
+        let last = match page.last() {
+            Some(m) => m.id,
+            None => break,
+        };
+        scanned += page.len();
+
+        for m in page {
+            if after.map_or(false, |a| m.id <= a) {
+                return Ok(out);
+            }
+
+            if for_user.map_or(true, |u| m.author.id == u) && filters.matches(&m) {
+                out.push(m);
+                if out.len() >= how_many {
+                    break;
+                }
+            }
+        }
+
+        cursor = last;
+    }
+
+    Ok(out)
+}
+
+/// The result of cleaning messages, which may have only partly succeeded.
+pub struct CleanedMessages {
+    /// The messages which were actually deleted.
+    pub deleted: Vec<Message>,
+    /// The first error hit while deleting messages, if any.
+    pub error: Option<crate::error::Error>,
+}
+
+/// Deletes messages in a channel, bulk deleting where Discord allows it and falling back to deleting
+/// messages one by one if they are too old to be bulk deleted.
+/// A failed deletion doesn't stop the rest, so the messages which were deleted can still be archived.
+#[allow(clippy::too_many_arguments)]
 pub async fn clean_messages(
     dis: &Dispatch,
     ctx: &Context,
-    how_many: ConstrainedU64<1, 100>,
-    before: chrono::DateTime<Utc>,
+    how_many: ConstrainedU64<1, MAX_CLEAN>,
+    before: MessageId,
     in_guild: GuildId,
     in_channel: VerifiedChannel,
     for_user: Option<VerifiedUser>,
-) -> crate::error::Result<usize> {
-    let cv = dis.message_cache().get(&in_guild).ok_or(GuildNotInCache)?;
-
-    let snapshot = cv.snapshot();
-
-    let relevant_messages = snapshot
-        .iter()
-        .rev()
-        .cloned()
-        .filter(|m| m.timestamp < before)
-        .filter(|m| m.channel == in_channel.into_inner());
-    let full_ids: Box<dyn Iterator<Item = MsgInfo> + Send> = if let Some(u) = for_user {
-        Box::new(relevant_messages.filter(move |m| m.user == u.into_inner()))
-    } else {
-        Box::new(relevant_messages)
+    filters: &CleanFilters,
+) -> crate::error::Result<CleanedMessages> {
+    let chan = in_channel.into_inner();
+    let how_many = how_many.to_usize().unwrap();
+    let for_user = for_user.map(|u| u.into_inner());
+    let cached = cached_messages_to_clean(dis, ctx, how_many, before, in_guild, chan, for_user, filters).await;
+    let msgs = match cached {
+        Some(msgs) => msgs,
+        None => collect_messages_to_clean(ctx, how_many, before, chan, for_user, filters).await?,
     };
 
-    let mids = full_ids.take(how_many.to_usize().unwrap()).collect::<Vec<_>>();
+    // Leave a little slack so messages don't age out between fetching and deleting them.
+    let bulk_cutoff = Utc::now() - chrono::Duration::days(BULK_DELETE_MAX_AGE_DAYS) + chrono::Duration::minutes(5);
+    let (recent, old): (Vec<Message>, Vec<Message>) = msgs.into_iter().partition(|m| m.timestamp > bulk_cutoff);
 
-    let v = match &mids[..] {
-        [] => 0,
-        [id] => in_channel.into_inner().delete_message(ctx, id.msg).await.map(|_| 1)?,
-        [vals @ ..] => in_channel
-            .into_inner()
-            .delete_messages(ctx, vals.iter().map(|m| m.msg))
-            .await
-            .map(|_| mids.len())?,
-    };
+    let mut deleted = Vec::with_capacity(recent.len() + old.len());
+    let mut error = None;
+    for chunk in recent.chunks(HISTORY_PAGE_SIZE as usize) {
+        let res = match chunk {
+            [m] => chan.delete_message(ctx, m.id).await,
+            ms => chan.delete_messages(ctx, ms.iter().map(|m| m.id)).await,
+        };
+        match res {
+            Ok(()) => deleted.extend_from_slice(chunk),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
 
-    cv.remove_all(mids.into_iter());
+    if !old.is_empty() {
+        debug!("deleting {} message(s) too old for bulk deletion", old.len());
+    }
+    for m in old {
+        match chan.delete_message(ctx, m.id).await {
+            Ok(()) => deleted.push(m),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    if let Some(cv) = dis.message_cache().get(&in_guild) {
+        cv.remove_all(deleted.iter().map(MsgInfo::from));
+    }
+
+    Ok(CleanedMessages {
+        deleted,
+        error: error.map(Into::into),
+    })
+}
+
+/// Renders deleted messages as plain text, oldest first.
+pub fn format_message_archive(msgs: &[Message]) -> String {
+    let mut out = String::new();
+    for m in msgs.iter().rev() {
+        out.push_str(&format!(
+            "[{}] {} ({}): {}\n",
+            m.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+            m.author.tag(),
+            m.author.id,
+            m.content
+        ));
+        for a in &m.attachments {
+            out.push_str(&format!("    attachment: {}\n", a.url));
+        }
+    }
+    out
+}
+
+/// Splits an archive into parts of at most `max` bytes, breaking between lines. A single line longer than `max`
+/// gets a part to itself.
+pub fn split_archive(archive: &str, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for line in archive.split_inclusive('\n') {
+        if end > start && end + line.len() - start > max {
+            parts.push(&archive[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    if end > start {
+        parts.push(&archive[start..end]);
+    }
+    parts
+}
+
+/// Posts an archive of cleaned messages to the mod log, split over several posts if it's too big for one.
+/// A failed post doesn't stop the rest; the first error is returned.
+async fn archive_cleaned_messages(
+    dis: &Dispatch,
+    ctx: &Context,
+    gid: GuildId,
+    channel: VerifiedChannel,
+    orig: &Message,
+    filters: &CleanFilters,
+    cleaned: &[Message],
+) -> crate::error::Result<()> {
+    if cleaned.is_empty() {
+        return Ok(());
+    }
+
+    let chan = channel.into_inner();
+    let archive = format_message_archive(cleaned);
+    let parts = split_archive(&archive, MAX_ARCHIVE_BYTES);
+    let filter_desc = filters.to_string();
+
+    let mut error = None;
+    for (i, part) in parts.iter().enumerate() {
+        let filename = if parts.len() > 1 {
+            format!("cleaned-{}-{}-{}.txt", chan, orig.id, i + 1)
+        } else {
+            format!("cleaned-{}-{}.txt", chan, orig.id)
+        };
+        let file = AttachmentType::Bytes {
+            data: part.as_bytes().to_vec().into(),
+            filename,
+        };
+
+        let res = send_file_to_mod_log(dis, ctx, gid, file, |e| {
+            e.title("Messages Cleaned")
+                .field("Moderator", orig.author.mention(), true)
+                .field("Channel", chan.mention(), true)
+                .field("Count", cleaned.len(), true);
+            if parts.len() > 1 {
+                e.field("Part", format!("{}/{}", i + 1, parts.len()), true);
+            }
+            if !filter_desc.is_empty() {
+                e.field("Filters", filter_desc.trim(), false);
+            }
+            e.timestamp(&Utc::now())
+        })
+        .await;
+        if let Err(e) = res {
+            error.get_or_insert(e);
+        }
+    }
 
-    Ok(v)
+    error.map_or(Ok(()), Err)
 }
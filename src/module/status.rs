@@ -85,6 +85,7 @@ impl Module for StatusModule {
 
         let commands_seen = self.command_counter.load(Ordering::Relaxed);
         let stats = dis.config_cache().statistics();
+        let messages = dis.message_cache().metrics();
 
         orig.channel_id
             .send_message(ctx, |e| {
@@ -107,6 +108,14 @@ impl Module for StatusModule {
                             format!("{} / {}", stats.misses, stats.accesses),
                             true,
                         )
+                        .field(
+                            "Message Cache",
+                            format!(
+                                "{} guilds, {} hits, {} misses, {} evicted",
+                                messages.size, messages.hits, messages.misses, messages.evictions
+                            ),
+                            false,
+                        )
                         .field("Uptime", pretty_elapsed, false)
                         .field("Sys Uptime", pretty_sys_uptime, false)
                         .field("Shard Id", shard, true)
@@ -0,0 +1,145 @@
+//! Contains logic for keeping certain roles on users who leave and rejoin a guild.
+//! Without this, a muted user could escape their mute by leaving and rejoining.
+
+use std::borrow::Borrow;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::user::User;
+use shrinkwraprs::Shrinkwrap;
+
+use crate::db::timed::{ActionKind, TimedEvents};
+use crate::db::DbContext;
+use crate::dispatch::config::{Value, VerifiedRole, VerifiedRoles};
+use crate::dispatch::Dispatch;
+use crate::error::GuildNotInCache;
+use crate::module::moderation::MUTE_ROLE;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// Re-applies sticky roles to members when they rejoin a guild.
+pub struct StickyRoleModule;
+
+/// Config key for the roles which should be re-applied when a user rejoins a guild.
+pub const STICKY_ROLES: &str = "sticky_roles";
+
+/// Wrapper around DbContext to store/retrieve the sticky roles of users who left a guild.
+#[derive(Shrinkwrap)]
+pub struct StickyRoles<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> StickyRoles<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        StickyRoles {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Stores roles which should be re-applied if the user rejoins.
+    pub async fn store_roles(&self, user: UserId, roles: &[RoleId]) -> crate::error::Result<()> {
+        self.ctx
+            .storage()
+            .store_sticky_roles(self.ctx.guild(), user, roles)
+            .await
+    }
+
+    /// Removes and returns all roles stored for a user.
+    pub async fn take_roles(&self, user: UserId) -> crate::error::Result<Vec<RoleId>> {
+        self.ctx.storage().take_sticky_roles(self.ctx.guild(), user).await
+    }
+
+    /// Removes a single stored role for a user, i.e. because a timed mute expired while they were gone.
+    pub async fn drop_role(&self, user: UserId, role: RoleId) -> crate::error::Result<()> {
+        self.ctx.storage().drop_sticky_role(self.ctx.guild(), user, role).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for StickyRoleModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("sticky-roles", "re-applies sticky roles to users who leave and rejoin.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_member_hooks(true)
+                .with_config_value(Value::<VerifiedRoles>::with_default(
+                    STICKY_ROLES,
+                    "A comma separated list of roles which are re-applied to users who leave and rejoin. The mute role is always sticky.",
+                    Default::default,
+                )
+                .with_example("Muted, Verified"))
+        });
+        &INFO
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        let db = dis.db(member.guild_id);
+        let roles = StickyRoles::new(db).take_roles(member.user.id).await?;
+        if roles.is_empty() {
+            return Ok(());
+        }
+
+        // Roles may have been deleted while the user was gone.
+        let guild = member.guild_id.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+        let roles: Vec<RoleId> = roles.into_iter().filter(|r| guild.roles.contains_key(r)).collect();
+
+        debug!("re-applying {} sticky role(s)", roles.len());
+        let mut member = member.clone();
+        member.add_roles(ctx, &roles).await?;
+        Ok(())
+    }
+
+    async fn on_member_leave(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        guild: GuildId,
+        user: &User,
+        member: Option<&Member>,
+    ) -> crate::error::Result<()> {
+        let db = dis.db(guild);
+        let mut sticky = dis
+            .config_value_t::<VerifiedRoles>(STICKY_ROLES)?
+            .get_or_default(&db)
+            .await?
+            .iter()
+            .collect::<Vec<_>>();
+
+        let mute_role = dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?.get(&db).await?;
+        if let Some(r) = &mute_role {
+            sticky.push(r.into_inner());
+        }
+
+        let mut roles: Vec<RoleId> = match member {
+            Some(m) => m.roles.iter().filter(|r| sticky.contains(r)).copied().collect(),
+            None => {
+                // Discord doesn't let us look up a member who has already left, so the best we can do is
+                // keep the roles we know they had from their pending timed actions, i.e. an active mute.
+                warn!("member wasn't cached; persisting sticky roles from pending actions only");
+                TimedEvents::new(db.clone())
+                    .actions_for_user(user.id)
+                    .await?
+                    .into_iter()
+                    .filter_map(|a| match a.kind() {
+                        ActionKind::Mute => mute_role.as_ref().map(|r| r.into_inner()),
+                        ActionKind::RemoveRole(r) => Some(r),
+                        _ => None,
+                    })
+                    .filter(|r| sticky.contains(r))
+                    .collect()
+            }
+        };
+        roles.sort_unstable();
+        roles.dedup();
+        if roles.is_empty() {
+            return Ok(());
+        }
+
+        debug!("persisting {} sticky role(s)", roles.len());
+        StickyRoles::new(db).store_roles(user.id, &roles).await
+    }
+}
@@ -0,0 +1,206 @@
+//! Contains the `whois` command, which summarises what Glimbot knows about a member.
+//! Anyone may look up a member's public details; moderation details are only shown to moderators.
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::misc::Mentionable;
+
+use crate::db::timed::{ActionKind as TimedActionKind, TimedEvents};
+use crate::dispatch::config::{FromStrWithCtx, RoleExt, VerifiedUser};
+use crate::dispatch::Dispatch;
+use crate::error::GuildNotInCache;
+use crate::module::privilege::authorized_sensitivities;
+use crate::module::risk::{risk_score, since, whole_minutes};
+use crate::module::spam::{PressureView, SpamConfig, SPAM_CONFIG_KEY};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Shows what Glimbot knows about a member.
+pub struct WhoisModule {
+    /// The live spam pressure tracked by the spam module.
+    pressure: PressureView,
+}
+
+impl WhoisModule {
+    /// Creates the module, reading spam pressure through the given view.
+    pub fn new(pressure: PressureView) -> Self {
+        WhoisModule { pressure }
+    }
+}
+
+/// Command to show information about a member.
+#[derive(Debug, structopt::StructOpt)]
+pub struct WhoisOpt {
+    /// The user to look up.
+    user: String,
+}
+
+/// The most characters Discord allows in an embed field's value.
+pub const MAX_FIELD_LEN: usize = 1024;
+
+/// Joins items with `sep`, leaving off as many as needed (and saying how many) to stay within `max_len` characters.
+pub fn join_truncated(items: &[String], sep: &str, max_len: usize) -> String {
+    let joined = items.join(sep);
+    if joined.chars().count() <= max_len {
+        return joined;
+    }
+
+    let sep_len = sep.chars().count();
+    let mut out = String::new();
+    let mut len = 0;
+    for (i, item) in items.iter().enumerate() {
+        let item_len = if i > 0 { sep_len } else { 0 } + item.chars().count();
+        // Leave room to say how many were left off.
+        let more_len = sep_len + format!("+{} more", items.len() - i - 1).len();
+
+        if len + item_len + more_len > max_len {
+            if i > 0 {
+                out.push_str(sep);
+            }
+            out.push_str(&format!("+{} more", items.len() - i));
+            break;
+        }
+
+        if i > 0 {
+            out.push_str(sep);
+        }
+        out.push_str(item);
+        len += item_len;
+    }
+    out
+}
+
+/// Formats a timestamp along with how long ago it was.
+fn with_age(t: chrono::DateTime<Utc>) -> String {
+    format!(
+        "{} ({} ago)",
+        t.format("%Y-%m-%d %H:%M UTC"),
+        humantime::format_duration(whole_minutes(since(t)))
+    )
+}
+
+/// Describes a pending timed action for display.
+async fn describe_timed(ctx: &Context, guild: GuildId, kind: TimedActionKind) -> String {
+    match kind {
+        TimedActionKind::Ban => "unban".to_string(),
+        TimedActionKind::Mute => "unmute".to_string(),
+        TimedActionKind::RemoveRole(r) => format!("remove {}", r.to_role_name_or_id(ctx, guild).await),
+        TimedActionKind::Debug => "debug".to_string(),
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for WhoisModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "whois",
+                "shows a member's join date, account age and roles; moderators also see their moderation history.",
+            )
+            .with_sensitivity(Sensitivity::Low)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = WhoisOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let user: UserId = VerifiedUser::from_str_with_ctx(&opts.user, ctx, gid)
+            .await?
+            .into_inner();
+
+        let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+        let caller = orig.member(ctx).await?;
+        let is_mod = authorized_sensitivities(dis, ctx, &guild, &caller)
+            .await?
+            .contains(&Sensitivity::High);
+
+        let target = user.to_user(ctx).await?;
+        let member = gid.member(ctx, user).await.ok();
+        let joined_at = member.as_ref().and_then(|m| m.joined_at);
+        let roles = member
+            .as_ref()
+            .map(|m| {
+                let mut roles: Vec<_> = m.roles.iter().filter_map(|r| guild.roles.get(r)).collect();
+                roles.sort_by_key(|r| std::cmp::Reverse(r.position));
+                let roles: Vec<_> = roles.into_iter().map(|r| r.mention().to_string()).collect();
+                join_truncated(&roles, " ", MAX_FIELD_LEN)
+            })
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "none".to_string());
+
+        let mut fields = vec![
+            ("User", target.mention().to_string(), true),
+            ("ID", user.to_string(), true),
+            ("Account created", with_age(user.created_at()), false),
+            (
+                "Joined",
+                joined_at.map(with_age).unwrap_or_else(|| "not a member".to_string()),
+                false,
+            ),
+            ("Roles", roles, false),
+        ];
+
+        if is_mod {
+            let conf = dis
+                .config_value_t::<SpamConfig>(SPAM_CONFIG_KEY)?
+                .get_or_default(&dis.db(gid))
+                .await?;
+            let pressure = self
+                .pressure
+                .current_pressure(gid, user, &conf)
+                .map_or(0.0, |p| p.raw());
+            let score = risk_score(dis, gid, user, joined_at, conf.max_pressure.raw()).await?;
+
+            let mut timed = Vec::new();
+            for a in TimedEvents::new(dis.db(gid)).actions_for_user(user).await? {
+                timed.push(format!(
+                    "{} at {}",
+                    describe_timed(ctx, gid, a.kind()).await,
+                    a.expiry().format("%Y-%m-%d %H:%M UTC")
+                ));
+            }
+            let timed = if timed.is_empty() {
+                "none".to_string()
+            } else {
+                join_truncated(&timed, "\n", MAX_FIELD_LEN)
+            };
+
+            fields.push((
+                "Spam pressure",
+                format!("{:.1} / {:.1}", pressure, conf.max_pressure.raw()),
+                true,
+            ));
+            fields.push(("Risk score", format!("{:.2}", score.total()), true));
+            fields.push((
+                "Prior actions",
+                format!("{} warns, {} mutes", score.record.warns, score.record.mutes),
+                true,
+            ));
+            fields.push(("Pending timed actions", timed, false));
+        }
+
+        orig.channel_id
+            .send_message(ctx, |m| {
+                m.reference_message(orig).embed(|e| {
+                    e.title(target.tag())
+                        .thumbnail(target.face())
+                        .fields(fields)
+                        .timestamp(&Utc::now())
+                })
+            })
+            .await?;
+        Ok(())
+    }
+}
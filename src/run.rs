@@ -2,7 +2,9 @@
 
 use serenity::client::bridge::gateway::GatewayIntents;
 
-use crate::dispatch::{ArcDispatch, ShardManKey};
+use crate::db::notify::ConfigListener;
+use crate::db::storage::PostgresStorage;
+use crate::dispatch::{ArcDispatch, Dispatch, ShardManKey};
 use crate::module::status::START_TIME;
 use once_cell::sync::Lazy;
 use tokio::sync::broadcast;
@@ -11,42 +13,63 @@ use tokio::sync::broadcast;
 pub static PANIC_ALERT_CHANNEL: Lazy<(broadcast::Sender<()>, broadcast::Receiver<()>)> =
     Lazy::new(|| broadcast::channel(100));
 
-/// Starts Glimbot.
-/// This is where modules are loaded.
-pub async fn start_bot() -> crate::error::Result<()> {
-    let pool = crate::db::create_pool().await?;
-    let mut dispatch = crate::dispatch::Dispatch::new(
-        std::env::var("GLIMBOT_OWNER")
-            .expect("Couldn't find owner information.")
-            .parse()
-            .expect("Invalid owner token."),
-        pool,
-    );
+/// Adds every module Glimbot ships with to a dispatch.
+pub fn load_modules(dispatch: &mut Dispatch) {
     dispatch.add_module(crate::module::base_filter::BaseFilter);
     dispatch.add_module(crate::module::owner::OwnerFilter);
     dispatch.add_module(crate::module::privilege::PrivilegeFilter);
     dispatch.add_module(crate::module::conf::ConfigModule);
+    dispatch.add_module(crate::module::confirm::ConfirmationModule);
     dispatch.add_module(crate::module::status::StatusModule::default());
     dispatch.add_module(crate::module::roles::RoleModule);
     dispatch.add_module(crate::module::moderation::ModerationModule);
-    dispatch.add_module(crate::module::spam::SpamModule::default());
+    let spam = crate::module::spam::SpamModule::default();
+    let pressure = spam.pressure_view();
+    dispatch.add_module(spam);
+    dispatch.add_module(crate::module::slowmode::SlowmodeModule::default());
+    dispatch.add_module(crate::module::risk::RiskModule);
     dispatch.add_module(crate::module::shutdown::Shutdown);
     dispatch.add_module(crate::module::roles::ModRoleModule);
+    dispatch.add_module(crate::module::sticky::StickyRoleModule);
+    dispatch.add_module(crate::module::whois::WhoisModule::new(pressure));
     dispatch.add_module(crate::module::mock_raid::MockRaidModule::default());
     dispatch.add_module(crate::module::info::HelpModule);
+}
+
+/// Starts Glimbot.
+pub async fn start_bot() -> crate::error::Result<()> {
+    let storage = crate::db::storage::connect_storage().await?;
+    let mut dispatch = crate::dispatch::Dispatch::new(
+        std::env::var("GLIMBOT_OWNER")
+            .expect("Couldn't find owner information.")
+            .parse()
+            .expect("Invalid owner token."),
+        storage,
+    );
+    load_modules(&mut dispatch);
 
     let dispatch = ArcDispatch::from(dispatch);
+    if let Some(pg) = dispatch.storage().downcast_ref::<PostgresStorage>() {
+        let config_listener = ConfigListener::connect(&dispatch, pg.pool()).await?;
+        tokio::spawn(config_listener.run());
+    }
 
     let mut client = serenity::Client::builder(std::env::var("GLIMBOT_TOKEN").expect("Didn't find a token."))
         .intents(
             GatewayIntents::privileged()
                 | GatewayIntents::GUILD_MESSAGES
+                | GatewayIntents::GUILD_MESSAGE_REACTIONS
                 | GatewayIntents::GUILD_BANS
                 | GatewayIntents::GUILDS
                 | GatewayIntents::DIRECT_MESSAGES,
         )
-        .event_handler(dispatch)
+        .event_handler(dispatch.clone())
         .await?;
+    client
+        .cache_and_http
+        .cache
+        .set_max_messages(crate::module::spam::CACHED_MESSAGES_PER_CHANNEL)
+        .await;
 
     let _ = START_TIME.elapsed();
     let shard_man = client.shard_manager.clone();
@@ -70,6 +93,9 @@ pub async fn start_bot() -> crate::error::Result<()> {
 
     dg.insert::<ShardManKey>(shard_man);
     std::mem::drop(dg);
-    client.start_autosharded().await?;
+    let res = client.start_autosharded().await;
+    info!("shards stopped, running shutdown hooks");
+    dispatch.run_shutdown_hooks().await;
+    res?;
     Ok(())
 }
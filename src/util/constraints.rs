@@ -1,4 +1,4 @@
-//! Contains constrained integer types, mostly useful as hard limits in module options.
+//! Contains constrained integer types, mostly useful as hard limits in module options, and constraints on fields of JSON-shaped config values.
 
 #![allow(clippy::from_over_into)]
 
@@ -193,3 +193,151 @@ impl<const MIN: i64, const MAX: i64> ToPrimitive for ConstrainedI64<MIN, MAX> {
 pub type AtLeastI64<const MIN: i64> = ConstrainedI64<MIN, { i64::MAX }>;
 /// Same as [`AtMostU64`], but for signed values.
 pub type AtMostI64<const MAX: i64> = ConstrainedI64<{ i64::MIN }, MAX>;
+
+/// A constraint on a field of a JSON-shaped config value, checked whenever the value is set.
+/// Fields are dotted paths; `*` matches every element of a list or object, and an empty path is the whole value.
+/// Fields which aren't present, i.e. optional fields which aren't set, aren't checked.
+#[derive(Debug, Copy, Clone)]
+pub enum FieldConstraint {
+    /// The field must be a number no less than `min` and no more than `max`.
+    Range {
+        /// The path to the field.
+        field: &'static str,
+        /// The smallest allowed value.
+        min: f64,
+        /// The largest allowed value.
+        max: f64,
+    },
+    /// The field must be one of the listed strings.
+    OneOf {
+        /// The path to the field.
+        field: &'static str,
+        /// The allowed values.
+        values: &'static [&'static str],
+    },
+}
+
+/// Represents a [`FieldConstraint`] failure.
+#[derive(Debug)]
+pub struct FieldConstraintFailure {
+    /// The constraint which was violated.
+    constraint: FieldConstraint,
+    /// The value which violated it.
+    val: serde_json::Value,
+}
+
+impl fmt::Display for FieldConstraintFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.constraint.field_name(),
+            self.constraint,
+            self.val
+        )
+    }
+}
+
+impl std::error::Error for FieldConstraintFailure {}
+
+impl From<FieldConstraintFailure> for Error {
+    fn from(e: FieldConstraintFailure) -> Self {
+        Error::from_err(e, true)
+    }
+}
+
+impl FieldConstraint {
+    /// Creates a constraint that a numeric field is at least `min`.
+    pub const fn at_least(field: &'static str, min: f64) -> Self {
+        FieldConstraint::Range {
+            field,
+            min,
+            max: f64::INFINITY,
+        }
+    }
+
+    /// Creates a constraint that a numeric field is within `[min, max]`.
+    pub const fn range(field: &'static str, min: f64, max: f64) -> Self {
+        FieldConstraint::Range { field, min, max }
+    }
+
+    /// Creates a constraint that a field is one of the listed strings.
+    pub const fn one_of(field: &'static str, values: &'static [&'static str]) -> Self {
+        FieldConstraint::OneOf { field, values }
+    }
+
+    /// The path to the constrained field.
+    pub fn field(&self) -> &'static str {
+        match self {
+            FieldConstraint::Range { field, .. } | FieldConstraint::OneOf { field, .. } => field,
+        }
+    }
+
+    /// The path to the constrained field for display, which is `value` for the whole value.
+    pub fn field_name(&self) -> &'static str {
+        match self.field() {
+            "" => "value",
+            f => f,
+        }
+    }
+
+    /// Checks every instance of the field in a value.
+    pub fn check(&self, v: &serde_json::Value) -> Result<(), FieldConstraintFailure> {
+        let fields: Vec<&str> = self.field().split('.').filter(|f| !f.is_empty()).collect();
+        let mut found = Vec::new();
+        values_at(v, &fields, &mut found);
+
+        match found.into_iter().find(|v| !self.allows(v)) {
+            Some(bad) => Err(FieldConstraintFailure {
+                constraint: *self,
+                val: bad.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a single value satisfies the constraint.
+    fn allows(&self, v: &serde_json::Value) -> bool {
+        match self {
+            FieldConstraint::Range { min, max, .. } => v.as_f64().map_or(false, |n| n >= *min && n <= *max),
+            FieldConstraint::OneOf { values, .. } => v.as_str().map_or(false, |s| values.contains(&s)),
+        }
+    }
+}
+
+/// Collects every value at a path, expanding `*` over lists and objects.
+fn values_at<'v>(v: &'v serde_json::Value, fields: &[&str], out: &mut Vec<&'v serde_json::Value>) {
+    let (first, rest) = match fields.split_first() {
+        Some(x) => x,
+        None => {
+            out.push(v);
+            return;
+        }
+    };
+
+    match (v, *first) {
+        (serde_json::Value::Array(a), "*") => a.iter().for_each(|v| values_at(v, rest, out)),
+        (serde_json::Value::Object(m), "*") => m.values().for_each(|v| values_at(v, rest, out)),
+        (serde_json::Value::Object(m), f) => {
+            if let Some(v) = m.get(f) {
+                values_at(v, rest, out)
+            }
+        }
+        (serde_json::Value::Array(a), f) => {
+            if let Some(v) = f.parse::<usize>().ok().and_then(|i| a.get(i)) {
+                values_at(v, rest, out)
+            }
+        }
+        _ => {}
+    }
+}
+
+impl fmt::Display for FieldConstraint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldConstraint::Range { min, max, .. } if max.is_infinite() => write!(f, "a number of at least {}", min),
+            FieldConstraint::Range { min, max, .. } => write!(f, "a number in range [{}, {}]", min, max),
+            FieldConstraint::OneOf { values, .. } => write!(f, "one of {}", values.join(", ")),
+        }
+    }
+}
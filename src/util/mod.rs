@@ -1,10 +1,15 @@
 //! Contains misc utility extension traits and types.
 
 use std::ffi::OsString;
+use std::str::FromStr;
 
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::model::id::{ChannelId, MessageId};
 use structopt::StructOpt;
 
 use crate::error::{IntoBotErr, UserError};
+use chrono::Utc;
 use noisy_float::types::R64;
 
 pub mod clock;
@@ -62,3 +67,64 @@ pub fn parse_nonnegative_real(s: &str) -> crate::error::Result<R64> {
         Ok(r)
     }
 }
+
+impl_err!(
+    InvalidTime,
+    "expected a time like \"2021-04-01T12:00:00Z\" or a duration before now like \"30m\"",
+    true
+);
+
+/// Parses either an RFC 3339 timestamp or a human readable duration, which is interpreted as that long ago.
+pub fn parse_time_or_ago(s: &str) -> crate::error::Result<chrono::DateTime<Utc>> {
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+
+    let d = humantime::parse_duration(s).map_err(|_| InvalidTime)?;
+    let d = chrono::Duration::from_std(d).map_err(|_| InvalidTime)?;
+    Utc::now().checked_sub_signed(d).ok_or_else(|| InvalidTime.into())
+}
+
+impl_err!(
+    InvalidMessageRef,
+    "expected a message ID or a message link like \"https://discord.com/channels/<guild>/<channel>/<message>\"",
+    true
+);
+
+/// Matches message links, capturing the channel and message IDs.
+static MESSAGE_LINK_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^<?https?://(?:\w+\.)?discord(?:app)?\.com/channels/(?:\d+|@me)/(\d+)/(\d+)>?$"#)
+        .expect("Invalid message link RE")
+});
+
+/// A reference to a message, given either as a bare message ID or as a message link.
+/// Bare IDs don't carry a channel, so callers have to supply a sensible default.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MessageRef {
+    /// The channel the message is in, if known.
+    pub channel: Option<ChannelId>,
+    /// The ID of the message.
+    pub message: MessageId,
+}
+
+impl FromStr for MessageRef {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(caps) = MESSAGE_LINK_RE.captures(s) {
+            let channel = caps[1].parse::<u64>().map_err(|_| InvalidMessageRef)?;
+            let message = caps[2].parse::<u64>().map_err(|_| InvalidMessageRef)?;
+            return Ok(MessageRef {
+                channel: Some(ChannelId(channel)),
+                message: MessageId(message),
+            });
+        }
+
+        let message = s.parse::<u64>().map_err(|_| InvalidMessageRef)?;
+        Ok(MessageRef {
+            channel: None,
+            message: MessageId(message),
+        })
+    }
+}
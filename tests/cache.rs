@@ -0,0 +1,346 @@
+//! Concurrency stress tests for the per-guild cache.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use futures::executor::block_on;
+use glimbot::db::cache::{Cache, LfuEvictionStrategy, LruEvictionStrategy, TimedCache};
+use glimbot::error::GuildNotInCache;
+use rayon::prelude::*;
+
+/// How many concurrent callers each stress test uses.
+const CALLERS: usize = 256;
+/// How many distinct keys the multi-key stress tests spread callers over.
+const KEYS: u64 = 16;
+/// How many threads the stress tests run on, regardless of how many cores are available.
+const THREADS: usize = 32;
+
+/// Runs a stress test on its own thread pool.
+fn stress(f: impl FnOnce() + Send) {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(THREADS)
+        .build()
+        .unwrap()
+        .install(f)
+}
+
+#[test]
+fn concurrent_misses_run_one_loader() {
+    let cache: Cache<u64, usize> = Cache::null();
+    let loads = AtomicUsize::new(0);
+    let mut values = Vec::new();
+
+    stress(|| {
+        values = (0..CALLERS)
+            .into_par_iter()
+            .map(|i| {
+                let v = block_on(cache.get_or_insert_with(&0, async {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    // Give the other callers time to pile up behind the loader.
+                    std::thread::sleep(Duration::from_millis(20));
+                    Ok(i)
+                }))
+                .unwrap();
+                *v
+            })
+            .collect();
+    });
+
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    assert!(values.iter().all(|v| *v == values[0]));
+}
+
+#[test]
+fn each_key_is_loaded_once() {
+    let cache: Cache<u64, u64> = Cache::null();
+    let loads: Vec<AtomicUsize> = (0..KEYS).map(|_| AtomicUsize::new(0)).collect();
+
+    stress(|| {
+        (0..CALLERS * KEYS as usize).into_par_iter().for_each(|i| {
+            let key = i as u64 % KEYS;
+            let v = block_on(cache.get_or_insert_with(&key, async {
+                loads[key as usize].fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(1));
+                Ok(key * 10)
+            }))
+            .unwrap();
+            assert_eq!(*v, key * 10);
+        })
+    });
+
+    assert!(loads.iter().all(|l| l.load(Ordering::SeqCst) == 1));
+}
+
+#[test]
+fn evicted_values_are_replaced() {
+    let cache: TimedCache<u64, u64> = TimedCache::new(Duration::from_millis(10));
+    cache.insert(&0, 1);
+    std::thread::sleep(Duration::from_millis(20));
+
+    let v = block_on(cache.get_or_insert_with(&0, async { Ok(2) })).unwrap();
+    assert_eq!(*v, 2);
+    assert_eq!(cache.get(&0).map(|v| *v), Some(2));
+
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(*cache.get_or_insert_sync(&0, || 3), 3);
+}
+
+#[test]
+fn failed_loads_are_retried() {
+    let cache: Cache<u64, u64> = Cache::null();
+    let res = block_on(cache.get_or_insert_with(&0, async { Err(GuildNotInCache.into()) }));
+    assert!(res.is_err());
+    assert!(cache.get(&0).is_none());
+
+    let v = block_on(cache.get_or_insert_with(&0, async { Ok(1) })).unwrap();
+    assert_eq!(*v, 1);
+}
+
+#[test]
+fn inserts_during_a_load_win() {
+    let cache: Arc<Cache<u64, u64>> = Arc::new(Cache::null());
+    let (started_tx, started_rx) = mpsc::channel();
+    let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+    let loader = {
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            let v = block_on(cache.get_or_insert_with(&0, async move {
+                started_tx.send(()).unwrap();
+                finish_rx.recv().unwrap();
+                Ok(1)
+            }))
+            .unwrap();
+            *v
+        })
+    };
+
+    started_rx.recv().unwrap();
+    cache.insert(&0, 2);
+    finish_tx.send(()).unwrap();
+
+    assert_eq!(loader.join().unwrap(), 2);
+    assert_eq!(cache.get(&0).map(|v| *v), Some(2));
+}
+
+#[test]
+fn reads_during_a_reload_dont_lose_it() {
+    let cache: Arc<TimedCache<u64, u64>> = Arc::new(TimedCache::new(Duration::from_millis(10)));
+    cache.insert(&0, 1);
+    std::thread::sleep(Duration::from_millis(20));
+
+    let loads = Arc::new(AtomicUsize::new(0));
+    let (started_tx, started_rx) = mpsc::channel();
+    let (finish_tx, finish_rx) = mpsc::channel::<()>();
+    let loader = {
+        let cache = cache.clone();
+        let loads = loads.clone();
+        std::thread::spawn(move || {
+            let v = block_on(cache.get_or_insert_with(&0, async move {
+                loads.fetch_add(1, Ordering::SeqCst);
+                started_tx.send(()).unwrap();
+                finish_rx.recv().unwrap();
+                Ok(2)
+            }))
+            .unwrap();
+            *v
+        })
+    };
+
+    // Reading the expired value while it's being reloaded drops it from the slot.
+    started_rx.recv().unwrap();
+    assert!(cache.get(&0).is_none());
+    finish_tx.send(()).unwrap();
+    assert_eq!(loader.join().unwrap(), 2);
+
+    // The reloaded value was still stored, so nobody has to load it again.
+    assert_eq!(cache.get(&0).map(|v| *v), Some(2));
+    let v = block_on(cache.get_or_insert_with(&0, async {
+        loads.fetch_add(1, Ordering::SeqCst);
+        Ok(3)
+    }))
+    .unwrap();
+    assert_eq!(*v, 2);
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn sweeps_during_a_reload_dont_lose_it() {
+    let cache: Arc<TimedCache<u64, u64>> = Arc::new(TimedCache::new(Duration::from_millis(10)));
+    cache.insert(&0, 1);
+    std::thread::sleep(Duration::from_millis(20));
+
+    let (started_tx, started_rx) = mpsc::channel();
+    let (finish_tx, finish_rx) = mpsc::channel::<()>();
+    let loader = {
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            block_on(cache.get_or_insert_with(&0, async move {
+                started_tx.send(()).unwrap();
+                finish_rx.recv().unwrap();
+                Ok(2)
+            }))
+            .unwrap();
+        })
+    };
+
+    // The slot holds an expired value, but it's being reloaded, so it has to stay.
+    started_rx.recv().unwrap();
+    assert_eq!(cache.sweep(), 0);
+    finish_tx.send(()).unwrap();
+    loader.join().unwrap();
+
+    assert_eq!(cache.get(&0).map(|v| *v), Some(2));
+}
+
+#[test]
+fn sweeps_dont_break_single_loads() {
+    let cache: TimedCache<u64, u64> = TimedCache::new(Duration::from_secs(60));
+    let loads: Vec<AtomicUsize> = (0..KEYS * 64).map(|_| AtomicUsize::new(0)).collect();
+    let done = AtomicBool::new(false);
+
+    stress(|| {
+        rayon::join(
+            || {
+                while !done.load(Ordering::SeqCst) {
+                    cache.sweep();
+                }
+            },
+            || {
+                (0..CALLERS * 64).into_par_iter().for_each(|i| {
+                    let key = i as u64 % (KEYS * 64);
+                    block_on(cache.get_or_insert_with(&key, async {
+                        loads[key as usize].fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(1));
+                        Ok(key)
+                    }))
+                    .unwrap();
+                });
+                done.store(true, Ordering::SeqCst);
+            },
+        );
+    });
+
+    assert!(loads.iter().all(|l| l.load(Ordering::SeqCst) == 1));
+}
+
+#[test]
+fn mixed_operations_stay_consistent() {
+    let cache: TimedCache<u64, u64> = TimedCache::new(Duration::from_millis(1));
+
+    stress(|| {
+        (0..CALLERS * 64).into_par_iter().for_each(|i| {
+            let key = i as u64 % KEYS;
+            // Every value stored for a key is a multiple of the key plus one, so readers can check they got
+            // a value for the right key.
+            let val = (key + 1) * (i as u64 + 1);
+            let got = match i % 5 {
+                0 => Some(*block_on(cache.get_or_insert_with(&key, async { Ok(val) })).unwrap()),
+                1 => Some(*cache.get_or_insert_sync(&key, || val)),
+                2 => {
+                    cache.insert(&key, val);
+                    None
+                }
+                3 => cache.remove(&key).map(|v| *v),
+                _ => cache.get(&key).map(|v| *v),
+            };
+            if let Some(v) = got {
+                assert_eq!(v % (key + 1), 0);
+            }
+        })
+    });
+}
+
+#[test]
+fn lru_evicts_least_recently_used() {
+    let cache: Cache<u64, u64, LruEvictionStrategy> = Cache::new(LruEvictionStrategy::new(10));
+    for k in 0..10 {
+        cache.insert(&k, k);
+    }
+    // Touch the oldest half so the newer half is evicted instead.
+    for k in 0..5 {
+        assert!(cache.get(&k).is_some());
+    }
+    cache.insert(&10, 10);
+
+    assert!(cache.len() <= 10);
+    assert!((0..5).all(|k| cache.get(&k).is_some()));
+    assert!(cache.get(&10).is_some());
+    assert!(cache.metrics().evictions > 0);
+}
+
+#[test]
+fn lfu_evicts_least_frequently_used() {
+    let cache: Cache<u64, u64, LfuEvictionStrategy> = Cache::new(LfuEvictionStrategy::new(10));
+    for k in 0..10 {
+        cache.insert(&k, k);
+        for _ in 0..k {
+            cache.get(&k);
+        }
+    }
+    cache.insert(&10, 10);
+
+    assert!(cache.len() <= 10);
+    assert!(cache.get(&0).is_none());
+    assert!((5..=10).all(|k| cache.get(&k).is_some()));
+}
+
+#[test]
+fn sweep_removes_expired_keys() {
+    let cache: TimedCache<u64, u64> = TimedCache::new(Duration::from_millis(10));
+    for k in 0..100 {
+        cache.insert(&k, k);
+    }
+    std::thread::sleep(Duration::from_millis(20));
+    cache.insert(&100, 100);
+
+    assert_eq!(cache.sweep(), 100);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.metrics().evictions, 100);
+}
+
+#[test]
+fn bounded_caches_stay_bounded() {
+    let cache: Cache<u64, u64, LruEvictionStrategy> = Cache::new(LruEvictionStrategy::new(100));
+
+    stress(|| {
+        (0..CALLERS * 64).into_par_iter().for_each(|i| {
+            let key = i as u64;
+            block_on(cache.get_or_insert_with(&key, async move { Ok(key) })).unwrap();
+            cache.get(&(key / 2));
+        })
+    });
+
+    cache.sweep();
+    assert!(cache.len() <= 100);
+    let metrics = cache.metrics();
+    assert_eq!(metrics.hits + metrics.misses, 2 * (CALLERS * 64) as u64);
+}
+
+#[test]
+fn sweeps_dont_lose_updates() {
+    let cache: Cache<u64, usize> = Cache::null();
+    let writes = CALLERS * 64;
+    let done = AtomicBool::new(false);
+
+    stress(|| {
+        rayon::join(
+            || {
+                // Every new key starts out as an empty slot, which a sweep would remove.
+                while !done.load(Ordering::SeqCst) {
+                    cache.sweep();
+                }
+            },
+            || {
+                (0..writes).into_par_iter().for_each(|i| {
+                    cache.update(&(i as u64 % 4096), |v| Some(v.copied().unwrap_or(0) + 1));
+                });
+                done.store(true, Ordering::SeqCst);
+            },
+        );
+    });
+
+    let total: usize = cache.entries().iter().map(|(_, v)| **v).sum();
+    assert_eq!(total, writes);
+}
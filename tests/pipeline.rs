@@ -0,0 +1,460 @@
+//! Runs messages through the whole dispatch pipeline against in-memory storage, with no database or Discord connection.
+//! Anything which would call Discord's API fails immediately, so tests check the state left behind rather than replies.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use glimbot::db::timed::{Action, ActionKind, TimedEvents};
+use glimbot::dispatch::config::{VerifiedChannel, VerifiedRole, VerifiedUser};
+use glimbot::dispatch::Dispatch;
+use glimbot::module::conf::ConfigHistory;
+use glimbot::module::risk::{risk_record, MemberRisk, RiskScore};
+use glimbot::module::roles::{cancel_role_removals, JoinableRoles};
+use glimbot::module::spam::{clean_messages, CleanFilters};
+use glimbot::module::sticky::{StickyRoleModule, StickyRoles};
+use glimbot::module::Module;
+use glimbot::run::load_modules;
+use glimbot::util::constraints::ConstrainedU64;
+use serde_json::json;
+use serenity::client::bridge::gateway::ShardMessenger;
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::event::{GuildCreateEvent, MessageCreateEvent};
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::user::User;
+use serenity::prelude::{RwLock, TypeMap};
+
+const GUILD: GuildId = GuildId(1);
+const OWNER: UserId = UserId(100);
+const GUILD_OWNER: UserId = UserId(200);
+const MEMBER: UserId = UserId(300);
+const CHANNEL: ChannelId = ChannelId(2);
+
+/// The ID of the next test message.
+static NEXT_MESSAGE: AtomicU64 = AtomicU64::new(1);
+
+/// Creates a dispatch with every module loaded, backed by in-memory storage.
+fn dispatch() -> Dispatch {
+    let mut dis = Dispatch::in_memory(OWNER);
+    load_modules(&mut dis);
+    dis
+}
+
+/// Creates a context which knows about [`GUILD`] but can't reach Discord. HTTP requests go through a proxy
+/// nothing listens on, so they fail straight away instead of leaving the machine.
+async fn offline_context() -> Context {
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap())
+        .build()
+        .unwrap();
+    let ctx = Context {
+        data: Arc::new(RwLock::new(TypeMap::new())),
+        shard: ShardMessenger::new(futures::channel::mpsc::unbounded().0),
+        shard_id: 0,
+        http: Arc::new(Http::new(Arc::new(client), "")),
+        cache: Arc::new(serenity::cache::Cache::new()),
+    };
+
+    let mut guild: GuildCreateEvent = serde_json::from_value(json!({
+        "id": GUILD.to_string(),
+        "name": "test guild",
+        "owner_id": GUILD_OWNER.to_string(),
+        "afk_timeout": 300,
+        "channels": [],
+        "default_message_notifications": 0,
+        "emojis": [],
+        "explicit_content_filter": 0,
+        "features": [],
+        "icon": null,
+        "joined_at": "2021-01-01T00:00:00+00:00",
+        "large": false,
+        "member_count": 0,
+        "members": [],
+        "mfa_level": 0,
+        "presences": [],
+        "region": "us-east",
+        "roles": [],
+        "splash": null,
+        "system_channel_id": null,
+        "verification_level": 0,
+        "voice_states": [],
+        "preferred_locale": "en-US",
+    }))
+    .unwrap();
+    ctx.cache.update(&mut guild).await;
+    ctx
+}
+
+/// Creates a message sent in [`CHANNEL`].
+fn message(author: UserId, content: &str) -> Message {
+    serde_json::from_value(json!({
+        "id": NEXT_MESSAGE.fetch_add(1, Ordering::Relaxed).to_string(),
+        "channel_id": CHANNEL.to_string(),
+        "guild_id": GUILD.to_string(),
+        "author": {
+            "id": author.to_string(),
+            "username": "someone",
+            "discriminator": "0001",
+            "avatar": null,
+            "bot": false,
+        },
+        "content": content,
+        "timestamp": Utc::now().to_rfc3339(),
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    }))
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_run_hooks() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+
+    for _ in 0..3 {
+        dis.handle_message(&ctx, &message(MEMBER, "hello")).await.unwrap();
+    }
+
+    let record = MemberRisk::new(dis.db(GUILD)).get(MEMBER).await.unwrap();
+    assert_eq!(record.messages, 3);
+    assert_eq!(risk_record(&dis, GUILD, MEMBER).await.unwrap().messages, 3);
+    assert!(dis.message_cache().get(&GUILD).is_some());
+
+    // Cached state belongs to the dispatch, not the process.
+    let other = dispatch();
+    assert_eq!(risk_record(&other, GUILD, MEMBER).await.unwrap().messages, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slowmode_failures_dont_stop_messages() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    let channels = json!({ CHANNEL.to_string(): {"threshold": 0, "max_delay": "1m"} });
+    dis.storage()
+        .set_config(GUILD, "slowmode_channels", &channels, None)
+        .await
+        .unwrap();
+
+    // Slowmode can't be raised without Discord, but the other hooks still run.
+    for _ in 0..2 {
+        dis.handle_message(&ctx, &message(MEMBER, "hello")).await.unwrap();
+    }
+    let record = MemberRisk::new(dis.db(GUILD)).get(MEMBER).await.unwrap();
+    assert_eq!(record.messages, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_are_filtered() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+
+    let err = dis
+        .handle_message(&ctx, &message(MEMBER, "!no-such-command"))
+        .await
+        .unwrap_err();
+    assert!(err.is_user_error());
+    assert_eq!(err.to_string(), "No such command: no-such-command");
+
+    let err = dis
+        .handle_message(&ctx, &message(MEMBER, "!shutdown"))
+        .await
+        .unwrap_err();
+    assert!(err.is_user_error());
+    assert!(err.to_string().contains("bot owner"));
+
+    let err = dis
+        .handle_message(&ctx, &message(MEMBER, "!config set command_prefix ?"))
+        .await
+        .unwrap_err();
+    assert!(err.is_user_error());
+    assert!(err.to_string().contains("privileged_role"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config_commands_change_storage() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+
+    // The value is stored before the reply, which can't be sent.
+    let res = dis
+        .handle_message(&ctx, &message(GUILD_OWNER, "!config set command_prefix ?"))
+        .await;
+    assert!(!res.unwrap_err().is_user_error());
+
+    let db = dis.db(GUILD);
+    let prefix = dis.config_value_t::<char>("command_prefix").unwrap();
+    assert_eq!(*prefix.get_or_default(&db).await.unwrap(), '?');
+    let history = ConfigHistory::new(&db).recent("command_prefix", 10).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].changed_by, Some(GUILD_OWNER));
+
+    // The old prefix no longer runs commands, but the new one does.
+    dis.handle_message(&ctx, &message(MEMBER, "!no-such-command"))
+        .await
+        .unwrap();
+    assert!(dis
+        .handle_message(&ctx, &message(MEMBER, "?no-such-command"))
+        .await
+        .is_err());
+
+    let res = dis
+        .handle_message(&ctx, &message(GUILD_OWNER, "?config reset command_prefix"))
+        .await;
+    assert!(!res.unwrap_err().is_user_error());
+    assert_eq!(*prefix.get_or_default(&db).await.unwrap(), '!');
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spam_config_constraints_are_enforced() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+
+    let rejected = [
+        ("spam_config.risk_multiplier", "-1"),
+        ("spam_config.pressure_decay", "-2.5"),
+        ("spam_config.role_multipliers", r#"[{"role": "1", "multiplier": -1}]"#),
+        (
+            "spam_config.responses",
+            r#"[{"pressure": 10, "action": "mute", "min_risk": 2}]"#,
+        ),
+        (
+            "spam_config.repeat_mutes",
+            r#"{"count": 0, "window": "1h", "action": "ban"}"#,
+        ),
+        (
+            "spam_config.repeat_mutes",
+            r#"{"count": 2, "window": "1h", "action": "mute"}"#,
+        ),
+    ];
+    for (key, value) in &rejected {
+        let cmd = format!("!config set -- {} '{}'", key, value);
+        let err = dis.handle_message(&ctx, &message(GUILD_OWNER, &cmd)).await.unwrap_err();
+        // The error should come from the field's constraint, not from parsing the command.
+        let field = key.trim_start_matches("spam_config.");
+        assert!(
+            err.is_user_error() && err.to_string().starts_with(field),
+            "{}: {}",
+            cmd,
+            err
+        );
+    }
+    // The spam hook stores the default config on the first message, and nothing else got through.
+    let default = dis.config_value("spam_config").unwrap().default_json().unwrap();
+    assert_eq!(dis.storage().get_config(GUILD, "spam_config").await.unwrap(), default);
+
+    // A valid value is stored, and only the reply fails.
+    let cmd = r#"!config set spam_config.repeat_mutes '{"count": 2, "window": "1h", "action": "ban"}'"#;
+    let res = dis.handle_message(&ctx, &message(GUILD_OWNER, cmd)).await;
+    assert!(!res.unwrap_err().is_user_error());
+    let stored = dis.storage().get_config(GUILD, "spam_config").await.unwrap().unwrap();
+    assert_eq!(stored["repeat_mutes"]["action"], json!("ban"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reverts_are_validated() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+
+    // Version 1 was stored without going through validation, so it breaks the spam config's constraints.
+    let default = dis.config_value("spam_config").unwrap().default_json().unwrap();
+    let mut invalid = default.clone().unwrap();
+    invalid["risk_multiplier"] = json!(-1.0);
+    dis.storage()
+        .set_config(GUILD, "spam_config", &invalid, None)
+        .await
+        .unwrap();
+    dis.storage().remove_config(GUILD, "spam_config", None).await.unwrap();
+
+    let err = dis
+        .handle_message(&ctx, &message(GUILD_OWNER, "!config revert spam_config 1"))
+        .await
+        .unwrap_err();
+    assert!(
+        err.is_user_error() && err.to_string().starts_with("risk_multiplier"),
+        "{}",
+        err
+    );
+    // Only the default the spam hook stored on seeing the command is left.
+    assert_eq!(dis.storage().get_config(GUILD, "spam_config").await.unwrap(), default);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config_values_are_cached() {
+    let dis = dispatch();
+    let db = dis.db(GUILD).with_actor(MEMBER);
+
+    db.insert("command_prefix", '$').await.unwrap();
+    let cached: Option<Arc<char>> = db.get("command_prefix").await.unwrap();
+    assert_eq!(cached.as_deref(), Some(&'$'));
+    assert_eq!(
+        dis.storage().get_config(GUILD, "command_prefix").await.unwrap(),
+        Some(json!("$"))
+    );
+
+    db.remove("command_prefix").await.unwrap();
+    let removed: Option<Arc<char>> = db.get("command_prefix").await.unwrap();
+    assert!(removed.is_none());
+    assert!(dis
+        .db(GuildId(2))
+        .get::<_, char>("command_prefix")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn joinable_roles_round_trip() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    let roles = JoinableRoles::new(dis.db(GUILD));
+
+    dis.storage().add_joinable_role(GUILD, RoleId(7)).await.unwrap();
+    dis.storage().add_joinable_role(GUILD, RoleId(5)).await.unwrap();
+    assert!(dis.storage().add_joinable_role(GUILD, RoleId(5)).await.is_err());
+    assert_eq!(roles.joinable_roles().await.unwrap(), vec![RoleId(5), RoleId(7)]);
+
+    // Listing the roles only fails once it tries to reply.
+    let res = dis.handle_message(&ctx, &message(MEMBER, "!role list-joinable")).await;
+    assert!(!res.unwrap_err().is_user_error());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timed_events_are_processed() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    let events = TimedEvents::new(dis.db(GUILD));
+
+    let due = Action::new(MEMBER, GUILD, ActionKind::Debug, Utc::now() - Duration::minutes(1));
+    let pending = Action::unmute(MEMBER, GUILD, Duration::hours(1));
+    events.store_action(&due).await.unwrap();
+    events.store_action(&pending).await.unwrap();
+    assert_eq!(events.actions_for_user(MEMBER).await.unwrap(), vec![due, pending]);
+
+    let batch = TimedEvents::get_actions_before(dis.storage(), Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(batch, vec![due]);
+    for a in batch {
+        a.act(&dis, &ctx).await.unwrap();
+    }
+    assert_eq!(events.actions_for_user(MEMBER).await.unwrap(), vec![pending]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spam_clean_uses_cached_messages() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    ctx.cache.set_max_messages(10).await;
+    for _ in 0..3 {
+        let msg = message(MEMBER, "spam");
+        let mut event: MessageCreateEvent = serde_json::from_value(serde_json::to_value(&msg).unwrap()).unwrap();
+        ctx.cache.update(&mut event).await;
+        dis.handle_message(&ctx, &msg).await.unwrap();
+    }
+    let before = message(GUILD_OWNER, "!spam clean 2").id;
+    let filters = CleanFilters::default();
+    let clean = |ctx| {
+        clean_messages(
+            &dis,
+            ctx,
+            ConstrainedU64::new(2).unwrap(),
+            before,
+            GUILD,
+            VerifiedChannel::from_known(CHANNEL),
+            Some(VerifiedUser::from_known(MEMBER)),
+            &filters,
+        )
+    };
+
+    // The caches cover the range, so only the deletions go to Discord, where they fail.
+    let cleaned = clean(&ctx).await.unwrap();
+    assert!(cleaned.deleted.is_empty());
+    assert!(cleaned.error.is_some());
+
+    // Without the messages' content, they have to be fetched from Discord, which fails outright.
+    let cold = offline_context().await;
+    assert!(clean(&cold).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uncached_leavers_keep_pending_mutes() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    let db = dis.db(GUILD);
+    let mute_role = RoleId(5);
+    dis.config_value_t::<VerifiedRole>("mute_role")
+        .unwrap()
+        .set(&db, VerifiedRole::from_known(mute_role))
+        .await
+        .unwrap();
+
+    let mut user = User::default();
+    user.id = MEMBER;
+    StickyRoleModule
+        .on_member_leave(&dis, &ctx, GUILD, &user, None)
+        .await
+        .unwrap();
+    assert!(StickyRoles::new(&db).take_roles(MEMBER).await.unwrap().is_empty());
+
+    TimedEvents::new(db.clone())
+        .store_action(&Action::unmute(MEMBER, GUILD, Duration::hours(1)))
+        .await
+        .unwrap();
+    StickyRoleModule
+        .on_member_leave(&dis, &ctx, GUILD, &user, None)
+        .await
+        .unwrap();
+    assert_eq!(StickyRoles::new(&db).take_roles(MEMBER).await.unwrap(), vec![mute_role]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reassigning_roles_cancels_their_expiry() {
+    let dis = dispatch();
+    let db = dis.db(GUILD);
+    let events = TimedEvents::new(db.clone());
+
+    let removal = Action::remove_role(MEMBER, GUILD, RoleId(5), Duration::hours(1));
+    let other_role = Action::remove_role(MEMBER, GUILD, RoleId(6), Duration::hours(1));
+    let mute = Action::unmute(MEMBER, GUILD, Duration::hours(1));
+    for a in &[removal, other_role, mute] {
+        events.store_action(a).await.unwrap();
+    }
+
+    cancel_role_removals(&db, MEMBER, RoleId(5)).await.unwrap();
+    let left = events.actions_for_user(MEMBER).await.unwrap();
+    assert_eq!(left.len(), 2);
+    assert!(!left.contains(&removal));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn established_members_arent_new() {
+    let dis = dispatch();
+    let ctx = offline_context().await;
+    let joined = Utc::now() - Duration::days(30);
+
+    let mut msg = message(MEMBER, "hello");
+    msg.member = serde_json::from_value(json!({
+        "deaf": false,
+        "joined_at": joined.to_rfc3339(),
+        "mute": false,
+        "nick": null,
+        "roles": [],
+    }))
+    .unwrap();
+    dis.handle_message(&ctx, &msg).await.unwrap();
+    assert!(dis.storage().risk_record(GUILD, MEMBER).await.unwrap().is_none());
+
+    let record = risk_record(&dis, GUILD, MEMBER).await.unwrap();
+    assert_eq!(RiskScore::new(MEMBER, Some(joined), record, 60.0).messages, 0.0);
+    let recent = Utc::now() - Duration::hours(1);
+    assert_eq!(RiskScore::new(MEMBER, Some(recent), record, 60.0).messages, 1.0);
+}
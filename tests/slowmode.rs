@@ -0,0 +1,96 @@
+//! Tests for automatic slowmode's settings and message rates.
+
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use chrono::Utc;
+use glimbot::dispatch::message_info::MsgInfo;
+use glimbot::dispatch::Dispatch;
+use glimbot::module::slowmode::{message_rate, ChannelSlowmode, SlowmodeChannels, MAX_SLOWMODE, RATE_WINDOW};
+use glimbot::util::ordset::OrdSet;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+
+const GUILD: GuildId = GuildId(1);
+const CHANNEL: ChannelId = ChannelId(2);
+const OTHER_CHANNEL: ChannelId = ChannelId(3);
+
+fn settings(min_delay: u64, max_delay: u64) -> ChannelSlowmode {
+    ChannelSlowmode {
+        threshold: 30,
+        cooldown: None,
+        min_delay: Duration::from_secs(min_delay),
+        max_delay: Duration::from_secs(max_delay),
+    }
+}
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+#[test]
+fn slowmode_doubles_within_bounds() {
+    let conf = settings(0, 60);
+    assert_eq!(conf.raised(secs(0)), secs(5));
+    assert_eq!(conf.raised(secs(5)), secs(10));
+    assert_eq!(conf.raised(secs(40)), secs(60));
+    assert_eq!(conf.raised(secs(60)), secs(60));
+
+    assert_eq!(settings(15, 60).raised(secs(0)), secs(15));
+    assert_eq!(settings(0, 100 * 60 * 60).raised(secs(4 * 60 * 60)), MAX_SLOWMODE);
+}
+
+#[test]
+fn slowmode_halves_down_to_min() {
+    let conf = settings(0, 60);
+    assert_eq!(conf.lowered(secs(60)), secs(30));
+    assert_eq!(conf.lowered(secs(10)), secs(5));
+    assert_eq!(conf.lowered(secs(5)), secs(0));
+    assert_eq!(conf.lowered(secs(0)), secs(0));
+
+    let conf = settings(20, 60);
+    assert_eq!(conf.lowered(secs(60)), secs(30));
+    assert_eq!(conf.lowered(secs(30)), secs(20));
+    assert_eq!(conf.lowered(secs(20)), secs(20));
+}
+
+#[test]
+fn cooldowns_must_be_below_thresholds() {
+    let parse = |s: &str| s.parse::<SlowmodeChannels>();
+    let conf = parse(r#"{"2": {"threshold": 30, "max_delay": "1m"}}"#).unwrap();
+    assert_eq!(conf.get(CHANNEL).unwrap().cooldown(), 15);
+    assert!(parse(r#"{"2": {"threshold": 30, "cooldown": 29, "max_delay": "1m"}}"#).is_ok());
+    assert!(parse(r#"{"2": {"threshold": 30, "cooldown": 30, "max_delay": "1m"}}"#).is_err());
+    assert!(parse(r#"{"2": {"threshold": 30, "cooldown": 45, "max_delay": "1m"}}"#).is_err());
+    assert!(parse(r#"{"2": {"threshold": 30, "min_delay": "2m", "max_delay": "1m"}}"#).is_err());
+}
+
+#[test]
+fn rates_count_recent_messages_in_the_channel() {
+    let dis = Dispatch::in_memory(UserId(100));
+    assert_eq!(message_rate(&dis, GUILD, CHANNEL), 0);
+
+    let now = Utc::now();
+    let old = now - chrono::Duration::from_std(RATE_WINDOW * 2).unwrap();
+    let messages = dis
+        .message_cache()
+        .get_or_insert_sync(&GUILD, || OrdSet::new(NonZeroUsize::new(4096)));
+    let mut id = 0;
+    let mut msg = |timestamp, channel| {
+        id += 1;
+        MsgInfo {
+            timestamp,
+            user: UserId(300),
+            channel,
+            msg: MessageId(id),
+        }
+    };
+    for _ in 0..3 {
+        messages.insert(msg(now, CHANNEL));
+        messages.insert(msg(old, CHANNEL));
+        messages.insert(msg(now, OTHER_CHANNEL));
+    }
+
+    assert_eq!(message_rate(&dis, GUILD, CHANNEL), 3);
+    assert_eq!(message_rate(&dis, GUILD, OTHER_CHANNEL), 3);
+    assert_eq!(message_rate(&dis, GuildId(2), CHANNEL), 0);
+}
@@ -0,0 +1,14 @@
+//! Tests for the parts of the spam module which don't need a Discord connection.
+
+use glimbot::module::spam::split_archive;
+
+#[test]
+fn archives_split_between_lines() {
+    let archive = "aaaa\nbbbb\ncccc\n";
+    assert_eq!(split_archive(archive, 100), vec![archive]);
+    assert_eq!(split_archive(archive, 10), vec!["aaaa\nbbbb\n", "cccc\n"]);
+    assert_eq!(split_archive(archive, 5), vec!["aaaa\n", "bbbb\n", "cccc\n"]);
+    // A line which is too long on its own still gets posted, in a part of its own.
+    assert_eq!(split_archive("a\nbbbbbbbb\nc\n", 4), vec!["a\n", "bbbbbbbb\n", "c\n"]);
+    assert!(split_archive("", 10).is_empty());
+}
@@ -0,0 +1,212 @@
+//! Behavioural tests for the storage backends which don't need a database server.
+
+use chrono::{Duration, Utc};
+use glimbot::db::storage::{MemoryStorage, SqliteStorage, Storage, MAX_JOINABLE_ROLES};
+use glimbot::db::timed::{Action, ActionKind};
+use serde_json::json;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+const GUILD: GuildId = GuildId(1);
+const OTHER_GUILD: GuildId = GuildId(2);
+const USER: UserId = UserId(10);
+
+/// Opens a fresh in-memory SQLite database.
+async fn sqlite() -> SqliteStorage {
+    SqliteStorage::connect("sqlite::memory:").await.unwrap()
+}
+
+async fn config_values_and_history(s: &dyn Storage) {
+    assert_eq!(s.get_config(GUILD, "a").await.unwrap(), None);
+    assert_eq!(s.get_or_insert_config(GUILD, "a", json!(1)).await.unwrap(), json!(1));
+    assert_eq!(s.get_or_insert_config(GUILD, "a", json!(2)).await.unwrap(), json!(1));
+
+    s.set_config(GUILD, "b", &json!({"x": 1}), Some(USER)).await.unwrap();
+    s.set_config(GUILD, "b", &json!({"x": 2}), None).await.unwrap();
+    assert_eq!(s.get_config(GUILD, "b").await.unwrap(), Some(json!({"x": 2})));
+    assert_eq!(s.get_config(OTHER_GUILD, "b").await.unwrap(), None);
+
+    s.remove_config(GUILD, "b", Some(USER)).await.unwrap();
+    assert_eq!(s.get_config(GUILD, "b").await.unwrap(), None);
+    // Removing an unset value isn't a change.
+    s.remove_config(GUILD, "b", Some(USER)).await.unwrap();
+
+    let history = s.config_history(GUILD, "b", 10).await.unwrap();
+    let versions: Vec<_> = history.iter().map(|c| c.version).collect();
+    assert_eq!(versions, vec![3, 2, 1]);
+    assert_eq!(history[0].old_value, Some(json!({"x": 2})));
+    assert_eq!(history[0].new_value, None);
+    assert_eq!(history[2].old_value, None);
+    assert_eq!(history[2].changed_by, Some(USER));
+    assert_eq!(history[1].changed_by, None);
+
+    let v2 = s.config_version(GUILD, "b", 2).await.unwrap().unwrap();
+    assert_eq!(v2.new_value, Some(json!({"x": 2})));
+    assert!(s.config_version(GUILD, "b", 4).await.unwrap().is_none());
+}
+
+async fn joinable_roles(s: &dyn Storage) {
+    s.add_joinable_role(GUILD, RoleId(5)).await.unwrap();
+    s.add_joinable_role(GUILD, RoleId(3)).await.unwrap();
+    assert!(s.add_joinable_role(GUILD, RoleId(3)).await.is_err());
+    assert!(s.is_joinable(GUILD, RoleId(3)).await.unwrap());
+    assert!(!s.is_joinable(OTHER_GUILD, RoleId(3)).await.unwrap());
+    assert_eq!(s.joinable_roles(GUILD).await.unwrap(), vec![RoleId(3), RoleId(5)]);
+
+    s.del_joinable_role(GUILD, RoleId(3)).await.unwrap();
+    assert_eq!(s.joinable_roles(GUILD).await.unwrap(), vec![RoleId(5)]);
+
+    for r in 1..MAX_JOINABLE_ROLES as u64 {
+        s.add_joinable_role(OTHER_GUILD, RoleId(r)).await.unwrap();
+    }
+    s.add_joinable_role(OTHER_GUILD, RoleId(1000)).await.unwrap();
+    assert!(s.add_joinable_role(OTHER_GUILD, RoleId(1001)).await.is_err());
+    assert_eq!(
+        s.joinable_roles(OTHER_GUILD).await.unwrap().len() as i64,
+        MAX_JOINABLE_ROLES
+    );
+}
+
+async fn imports_are_atomic(s: &dyn Storage) {
+    s.set_config(GUILD, "a", &json!(1), None).await.unwrap();
+    s.add_joinable_role(GUILD, RoleId(1)).await.unwrap();
+
+    let too_many: Vec<_> = (0..=MAX_JOINABLE_ROLES as u64).map(RoleId).collect();
+    let values = vec![("a".to_string(), json!(2))];
+    assert!(s
+        .import_config(GUILD, &values, Some(&too_many), Some(USER))
+        .await
+        .is_err());
+    assert_eq!(s.get_config(GUILD, "a").await.unwrap(), Some(json!(1)));
+    assert_eq!(s.joinable_roles(GUILD).await.unwrap(), vec![RoleId(1)]);
+
+    s.import_config(GUILD, &values, Some(&[RoleId(2), RoleId(3)]), Some(USER))
+        .await
+        .unwrap();
+    assert_eq!(s.get_config(GUILD, "a").await.unwrap(), Some(json!(2)));
+    assert_eq!(s.joinable_roles(GUILD).await.unwrap(), vec![RoleId(2), RoleId(3)]);
+    assert_eq!(s.config_history(GUILD, "a", 1).await.unwrap()[0].changed_by, Some(USER));
+
+    s.import_config(GUILD, &[], None, None).await.unwrap();
+    assert_eq!(s.joinable_roles(GUILD).await.unwrap(), vec![RoleId(2), RoleId(3)]);
+}
+
+async fn timed_events(s: &dyn Storage) {
+    let now = Utc::now();
+    let soon = Action::new(USER, GUILD, ActionKind::Mute, now - Duration::minutes(1));
+    let later = Action::new(USER, GUILD, ActionKind::RemoveRole(RoleId(4)), now + Duration::hours(1));
+    let elsewhere = Action::new(USER, OTHER_GUILD, ActionKind::Ban, now - Duration::minutes(2));
+    for a in &[later, soon, elsewhere] {
+        s.store_action(a).await.unwrap();
+    }
+    // The same action can't be stored twice.
+    assert!(s.store_action(&soon).await.is_err());
+
+    let mine = s.actions_for_user(GUILD, USER).await.unwrap();
+    assert_eq!(mine.len(), 2);
+    assert_eq!(mine[0].kind(), ActionKind::Mute);
+    assert_eq!(mine[1].kind(), ActionKind::RemoveRole(RoleId(4)));
+
+    let due = s.actions_before(now, 10).await.unwrap();
+    assert_eq!(due.len(), 2);
+    assert_eq!(due[0].guild(), OTHER_GUILD);
+    assert_eq!(s.actions_before(now, 1).await.unwrap().len(), 1);
+
+    for a in due {
+        s.drop_action(&a).await.unwrap();
+    }
+    assert!(s.actions_before(now, 10).await.unwrap().is_empty());
+    assert_eq!(s.actions_for_user(GUILD, USER).await.unwrap().len(), 1);
+}
+
+async fn sticky_roles(s: &dyn Storage) {
+    s.store_sticky_roles(GUILD, USER, &[RoleId(1), RoleId(2)])
+        .await
+        .unwrap();
+    s.store_sticky_roles(GUILD, USER, &[RoleId(2), RoleId(3)])
+        .await
+        .unwrap();
+    s.drop_sticky_role(GUILD, USER, RoleId(1)).await.unwrap();
+
+    let mut roles = s.take_sticky_roles(GUILD, USER).await.unwrap();
+    roles.sort_unstable();
+    assert_eq!(roles, vec![RoleId(2), RoleId(3)]);
+    assert!(s.take_sticky_roles(GUILD, USER).await.unwrap().is_empty());
+}
+
+async fn risk_records(s: &dyn Storage) {
+    assert!(s.risk_record(GUILD, USER).await.unwrap().is_none());
+    for _ in 0..5 {
+        s.record_risk_message(GUILD, USER, 3).await.unwrap();
+    }
+    s.record_risk_action(GUILD, USER, 1, 0).await.unwrap();
+    s.record_risk_action(GUILD, USER, 1, 2).await.unwrap();
+    s.record_peak_pressures(GUILD, &[(USER, 5.0), (UserId(11), 2.0)])
+        .await
+        .unwrap();
+    s.record_peak_pressures(GUILD, &[(USER, 4.0)]).await.unwrap();
+
+    let r = s.risk_record(GUILD, USER).await.unwrap().unwrap();
+    assert_eq!((r.messages, r.warns, r.mutes), (3, 2, 2));
+    assert_eq!(r.peak_pressure, 5.0);
+    let other = s.risk_record(GUILD, UserId(11)).await.unwrap().unwrap();
+    assert_eq!((other.messages, other.peak_pressure), (0, 2.0));
+}
+
+async fn spam_pressure(s: &dyn Storage) {
+    let t = Utc::now();
+    s.replace_spam_pressure(GUILD, &[(USER, 1.5, t), (UserId(11), 2.5, t)])
+        .await
+        .unwrap();
+    s.replace_spam_pressure(GUILD, &[(USER, 3.5, t)]).await.unwrap();
+
+    let stored = s.load_spam_pressure(GUILD).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].0, USER);
+    assert_eq!(stored[0].1, 3.5);
+    assert!((stored[0].2 - t).num_milliseconds().abs() < 1);
+    assert!(s.load_spam_pressure(OTHER_GUILD).await.unwrap().is_empty());
+}
+
+/// Runs each of the given scenarios against a backend, in a module named after it.
+macro_rules! backend_tests {
+    ($backend:ident, $storage:expr, [$($scenario:ident),* $(,)?]) => {
+        mod $backend {
+            use super::*;
+
+            $(
+                #[tokio::test(flavor = "multi_thread")]
+                async fn $scenario() {
+                    super::$scenario(&$storage).await;
+                }
+            )*
+        }
+    };
+}
+
+backend_tests!(
+    sqlite,
+    sqlite().await,
+    [
+        config_values_and_history,
+        joinable_roles,
+        imports_are_atomic,
+        timed_events,
+        sticky_roles,
+        risk_records,
+        spam_pressure,
+    ]
+);
+
+backend_tests!(
+    memory,
+    MemoryStorage::default(),
+    [
+        config_values_and_history,
+        joinable_roles,
+        imports_are_atomic,
+        timed_events,
+        sticky_roles,
+        risk_records,
+        spam_pressure,
+    ]
+);
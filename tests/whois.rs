@@ -0,0 +1,18 @@
+//! Tests for formatting `whois` output.
+
+use glimbot::module::whois::join_truncated;
+
+#[test]
+fn long_lists_are_truncated() {
+    let items: Vec<String> = (0..5).map(|i| format!("item{}", i)).collect();
+    assert_eq!(join_truncated(&items, " ", 100), "item0 item1 item2 item3 item4");
+    assert_eq!(join_truncated(&items, " ", 29), "item0 item1 item2 item3 item4");
+    assert_eq!(join_truncated(&items, " ", 28), "item0 item1 item2 +2 more");
+    assert_eq!(join_truncated(&items, " ", 5), "+5 more");
+    assert!(join_truncated(&[], " ", 5).is_empty());
+
+    let many: Vec<String> = (0..500).map(|i| format!("<@&{}>", 1_000_000 + i)).collect();
+    let joined = join_truncated(&many, " ", 1024);
+    assert!(joined.chars().count() <= 1024);
+    assert!(joined.ends_with(" more"));
+}